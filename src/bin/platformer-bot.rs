@@ -0,0 +1,78 @@
+// platformer-bot: prove that levels can be completed by searching for inputs that win
+//
+// usage: platformer-bot [--nodes N] [--replays DIRECTORY] [FILE or DIRECTORY]...
+// without files every level of every level pack is searched
+// prints a JSON array with one report per level, with the winning inputs and the coins no run reached,
+// and exits with 1 if any level could not be completed
+
+use serde::Serialize;
+
+use platformer::{ bot, levels, map };
+
+const DEFAULT_NODES: usize = 20000;
+
+#[derive(Serialize)]
+struct Report {
+    file: String,
+    error: Option<String>,  // the level failed to load
+    solved: bool,
+    verified: bool,  // playing the replay from the start wins too
+    ticks: Option<u32>,
+    solution: Option<bot::Solution>,
+}
+
+fn check(file: String, max_nodes: usize, replays: &Option<String>) -> Report {
+    let map = match map::from_file(file.clone()) {
+        Ok(map) => map,
+        Err(err) => {
+            return Report { file, error: Some(err.to_string()), solved: false, verified: false, ticks: None, solution: None };
+        }
+    };
+    let solution = bot::solve(&map, max_nodes);
+    let verified = solution.replay.as_ref().map_or(false, |replay| bot::play(&map, replay) == bot::Outcome::Won);
+    if let (Some(dir), Some(replay)) = (replays, &solution.replay) {
+        let stem = std::path::Path::new(&file).file_stem().unwrap().to_string_lossy().to_string();
+        let path = std::path::Path::new(dir).join(stem + ".replay.ron");
+        let written = std::fs::create_dir_all(dir)
+            .map_err(|err| err.to_string())
+            .and_then(|_| ron::ser::to_string_pretty(replay, ron::ser::PrettyConfig::default()).map_err(|err| err.to_string()))
+            .and_then(|contents| std::fs::write(&path, contents).map_err(|err| err.to_string()));
+        if let Err(err) = written {
+            eprintln!("Failed to write replay \"{}\": {}", path.display(), err);
+        }
+    }
+    Report {
+        file, error: None, solved: solution.solved, verified,
+        ticks: solution.replay.as_ref().map(|replay| replay.ticks()), solution: Some(solution),
+    }
+}
+
+fn main() {
+    let mut max_nodes = DEFAULT_NODES;
+    let mut replays: Option<String> = None;
+    let mut files: Vec<String> = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--nodes" => {
+                max_nodes = match args.next().and_then(|value| value.parse().ok()) {
+                    Some(value) => value,
+                    None => {
+                        eprintln!("--nodes needs a number");
+                        std::process::exit(2);
+                    }
+                };
+            }
+            "--replays" => { replays = args.next(); }
+            _ => { files.push(arg); }
+        }
+    }
+
+    let reports: Vec<Report> = levels::level_files(files).into_iter()
+        .map(|file| check(file, max_nodes, &replays))
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+    if reports.iter().any(|report| !report.solved) {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,33 @@
+// platformer-diff: compare two versions of a level by object
+//
+// usage: platformer-diff OLD NEW [--json]
+// prints the added, removed, moved, resized and recolored objects and the changed tracks and commands
+// exits with 0 if the levels are the same, 1 if they differ and 2 if a level fails to load
+
+use platformer::diff;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|arg| arg == "--json");
+    let files: Vec<String> = args.into_iter().filter(|arg| arg != "--json").collect();
+    if files.len() != 2 {
+        eprintln!("usage: platformer-diff OLD NEW [--json]");
+        std::process::exit(2);
+    }
+
+    let result = match diff::diff_files(files[0].clone(), files[1].clone()) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("Failed to compare the levels: {}", err);
+            std::process::exit(2);
+        }
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&result).unwrap());
+    } else {
+        print!("{}", result);
+    }
+    if !result.changes.is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,16 @@
+// platformer-lint: check level files without opening a window
+//
+// usage: platformer-lint [FILE or DIRECTORY]...
+// without arguments every level of every level pack is checked
+// prints a JSON array with one report per level and exits with 1 if any level has errors
+
+use platformer::{ levels, lint };
+
+fn main() {
+    let files = levels::level_files(std::env::args().skip(1).collect());
+    let reports: Vec<lint::Report> = files.into_iter().map(lint::lint_file).collect();
+    println!("{}", serde_json::to_string_pretty(&reports).unwrap());
+    if reports.iter().any(|report| !report.ok) {
+        std::process::exit(1);
+    }
+}
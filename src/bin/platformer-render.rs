@@ -0,0 +1,36 @@
+// platformer-render: draw a level to a PNG or SVG file without opening a window
+//
+// usage: platformer-render LEVEL OUTPUT [WIDTHxHEIGHT]
+// the format is chosen by the extension of OUTPUT, PNG images are scaled to fit into the given size
+// (the size of the map by default)
+
+use platformer::{ map, render };
+
+fn run(args: Vec<String>) -> Result<(), Box<dyn std::error::Error>> {
+    if args.len() < 2 {
+        return Err("usage: platformer-render LEVEL OUTPUT [WIDTHxHEIGHT]".into());
+    }
+    let map = map::from_file(args[0].clone())?;
+    let max_size = match args.get(2) {
+        Some(size) => match size.split_once('x') {
+            Some((width, height)) => (width.parse()?, height.parse()?),
+            None => { return Err(format!("\"{}\" is not a size, use WIDTHxHEIGHT", size).into()); }
+        },
+        None => map.size,
+    };
+    if args[1].ends_with(".svg") {
+        std::fs::write(&args[1], render::to_svg(&map))?;
+    } else if args[1].ends_with(".png") {
+        std::fs::write(&args[1], render::rasterize(&map, max_size).to_png())?;
+    } else {
+        return Err(format!("\"{}\" is neither .png nor .svg", args[1]).into());
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(err) = run(std::env::args().skip(1).collect()) {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
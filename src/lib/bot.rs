@@ -0,0 +1,264 @@
+use std::cmp::Reverse;
+use std::collections::{ BTreeSet, BinaryHeap, HashSet };
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use serde::{Deserialize, Serialize};
+use amethyst::{
+    core::Parent,
+    ecs::WorldExt,
+    prelude::*,
+    ui::{ UiImage, UiTransform },
+};
+
+use crate::components;
+use crate::map;
+use crate::physics;
+use crate::snapshot;
+
+// Replay: the inputs of a run, each held for a number of ticks
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Replay {
+    pub inputs: Vec<(physics::Input, u32)>,
+}
+
+impl Replay {
+    pub fn ticks(&self) -> u32 {
+        self.inputs.iter().map(|(_, ticks)| ticks).sum()
+    }
+
+    fn push(&mut self, input: physics::Input, ticks: u32) {
+        match self.inputs.last_mut() {
+            Some((last, last_ticks)) if *last == input => { *last_ticks += ticks; }
+            _ => { self.inputs.push((input, ticks)); }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Playing, Won, Dead,
+}
+
+// Sim: a level running without a window, with the same rules as GameState
+// dying ends the run, lives and checkpoints are not used
+pub struct Sim {
+    pub world: World,
+    map: map::Map,
+}
+
+impl Sim {
+    pub fn new(map: map::Map) -> Self {
+        let mut world = World::new();
+        world.register::<UiTransform>();
+        world.register::<UiImage>();
+        world.register::<Parent>();
+        components::register(&mut world);
+        map.initialize_objects(&mut world);
+        Sim { world, map }
+    }
+
+    // one fixed update, returns the coins collected on the way
+    pub fn step(&mut self, input: &physics::Input) -> (Outcome, Vec<String>) {
+        physics::step(&mut self.world, input);
+        let contacts = physics::check_contacts(&self.world);
+        if contacts.dead || physics::hurt(&mut self.world, contacts.hits) {
+            return (Outcome::Dead, vec![]);
+        }
+        if contacts.won {
+            return (Outcome::Won, contacts.coins);
+        }
+        physics::remove_objs(&mut self.world, contacts.projectiles);
+        physics::stomp(&mut self.world, contacts.stomps);
+        physics::remove_objs(&mut self.world, contacts.coins.clone());
+        physics::collect_keys(&mut self.world, contacts.keys);
+        self.world.maintain();
+        (Outcome::Playing, contacts.coins)
+    }
+
+    pub fn save(&self) -> snapshot::Snapshot {
+        snapshot::Snapshot::capture(&self.world, &self.map, 0)
+    }
+
+    pub fn load(&mut self, snapshot: &snapshot::Snapshot) {
+        snapshot.restore(&mut self.world, &self.map);
+        self.world.maintain();
+    }
+}
+
+// play a replay from the start
+pub fn play(map: &map::Map, replay: &Replay) -> Outcome {
+    let mut sim = Sim::new(map.clone());
+    for (input, ticks) in replay.inputs.iter() {
+        for _ in 0..*ticks {
+            match sim.step(input) {
+                (Outcome::Playing, _) => {}
+                (outcome, _) => { return outcome; }
+            }
+        }
+    }
+    Outcome::Playing
+}
+
+// every input is held for this many ticks, which keeps the search tree small
+const ACTION_TICKS: u32 = 4;
+// states closer than this (in unscaled pixels) count as the same
+const GRID: i32 = 4;
+
+fn actions() -> Vec<physics::Input> {
+    let input = |left, right, jump, drop| physics::Input { left, right, jump, drop };
+    vec![
+        input(false, true, false, false), input(true, false, false, false),
+        input(false, true, true, false), input(true, false, true, false),
+        input(false, false, true, false), input(false, false, false, true),
+        input(false, false, false, false),
+    ]
+}
+
+// what makes two states different for the search: the player, the interactables,
+// the removed objects and where the moving objects are, all positions rounded to the grid
+// timers of springs and hazards, projectiles, turret cooldowns and the ticks of tracks are left out to keep the
+// search small, so states that only differ in those are merged
+fn state_key(snapshot: &snapshot::Snapshot) -> u64 {
+    let grid = |pos: (i32, i32)| (pos.0.div_euclid(GRID), pos.1.div_euclid(GRID));
+    let player = snapshot.player.as_ref().map(|player| (grid(player.pos), ron::to_string(&player.comp).unwrap()));
+    let movables: Vec<(i32, i32)> = snapshot.movables.values().map(|movable| grid(movable.pos)).collect();
+    let enemies: Vec<(i32, i32)> = snapshot.enemies.values().map(|enemy| grid(enemy.pos)).collect();
+    let mut hasher = DefaultHasher::new();
+    (player, &snapshot.states, &snapshot.removed, movables, enemies, snapshot.spawned.len()).hash(&mut hasher);
+    hasher.finish()
+}
+
+struct Node {
+    snapshot: Option<snapshot::Snapshot>,  // dropped once the node is expanded
+    parent: Option<usize>,
+    input: physics::Input,
+    ticks: u32,  // the input is held for this many ticks after the parent
+    depth: u32,  // ticks from the start
+}
+
+// The result of searching a level
+#[derive(Debug, Serialize)]
+pub struct Solution {
+    pub solved: bool,
+    pub complete: bool,  // no state was left to explore, but merged states may hide runs (see state_key)
+    pub nodes: usize,
+    pub replay: Option<Replay>,  // the first winning run found
+    pub unreachable_coins: Vec<String>,  // coins not collected on any explored run, not a proof that they cannot be
+}
+
+// the trace of inputs from the start to a node
+fn trace(nodes: &Vec<Node>, mut index: usize) -> Replay {
+    let mut steps = vec![];
+    while let Some(parent) = nodes[index].parent {
+        steps.push((nodes[index].input, nodes[index].ticks));
+        index = parent;
+    }
+    let mut replay = Replay::default();
+    for (input, ticks) in steps.into_iter().rev() {
+        replay.push(input, ticks);
+    }
+    replay
+}
+
+// best-first search over inputs, always expanding the state closest to a target or a coin not collected yet
+// it keeps going after a win to find the coins until the search is complete or max_nodes states are visited
+pub fn solve(map: &map::Map, max_nodes: usize) -> Solution {
+    let mut sim = Sim::new(map.clone());
+    let all_coins: BTreeSet<String> = map.coins.iter().map(|coin| coin.name.clone()).collect();
+    let mut collected: BTreeSet<String> = BTreeSet::new();
+    let mut replay: Option<Replay> = None;
+
+    // distance from the player to the nearest target or coin still to collect
+    let center = |pos: (i32, i32), size: (u32, u32)| (pos.0 + size.0 as i32 / 2, pos.1 - size.1 as i32 / 2);
+    let distance = |snapshot: &snapshot::Snapshot, collected: &BTreeSet<String>| -> i64 {
+        let player = match &snapshot.player {
+            Some(player) => center(player.pos, map.player.size),
+            None => { return i64::MAX; }
+        };
+        map.targets.iter().map(|obj| center(obj.pos, obj.size))
+            .chain(map.coins.iter().filter(|obj| !collected.contains(&obj.name)).map(|obj| center(obj.pos, obj.size)))
+            .map(|pos| (pos.0 - player.0).abs() as i64 + (pos.1 - player.1).abs() as i64)
+            .min()
+            .unwrap_or(0)
+    };
+
+    let start = sim.save();
+    let mut seen: HashSet<u64> = HashSet::new();
+    seen.insert(state_key(&start));
+    let mut frontier = BinaryHeap::new();
+    frontier.push(Reverse((distance(&start, &collected), 0)));
+    let mut nodes = vec![Node { snapshot: Some(start), parent: None, input: physics::Input::default(), ticks: 0, depth: 0 }];
+
+    let mut complete = false;
+    while nodes.len() < max_nodes && !(replay.is_some() && collected == all_coins) {
+        let index = match frontier.pop() {
+            Some(Reverse((_, index))) => index,
+            None => { complete = true; break; }
+        };
+        let snapshot = nodes[index].snapshot.take().unwrap();
+        for input in actions() {
+            sim.load(&snapshot);
+            let mut outcome = Outcome::Playing;
+            let mut ticks = 0;
+            while ticks < ACTION_TICKS && outcome == Outcome::Playing {
+                let (result, coins) = sim.step(&input);
+                collected.extend(coins);
+                outcome = result;
+                ticks += 1;
+            }
+            let depth = nodes[index].depth + ticks;
+            match outcome {
+                Outcome::Dead => {}
+                Outcome::Won => {
+                    if replay.is_none() {
+                        nodes.push(Node { snapshot: None, parent: Some(index), input, ticks, depth });
+                        replay = Some(trace(&nodes, nodes.len() - 1));
+                    }
+                }
+                Outcome::Playing => {
+                    let next = sim.save();
+                    if !seen.insert(state_key(&next)) { continue; }
+                    frontier.push(Reverse((distance(&next, &collected).saturating_add(depth as i64), nodes.len())));
+                    nodes.push(Node { snapshot: Some(next), parent: Some(index), input, ticks, depth });
+                }
+            }
+        }
+    }
+
+    Solution {
+        solved: replay.is_some(), complete, nodes: nodes.len(), replay,
+        unreachable_coins: all_coins.difference(&collected).cloned().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replays_merge_repeated_inputs() {
+        let right = physics::Input { right: true, ..Default::default() };
+        let mut replay = Replay::default();
+        replay.push(right, 4);
+        replay.push(right, 4);
+        replay.push(physics::Input::default(), 2);
+        assert_eq!(replay.inputs, vec![(right, 8), (physics::Input::default(), 2)]);
+        assert_eq!(replay.ticks(), 10);
+    }
+
+    #[test]
+    fn solvable_levels_are_solved() {
+        let map = map::from_file("assets/levels/1.ron".to_string()).unwrap();
+        let solution = solve(&map, 20000);
+        assert!(solution.solved);
+        let replay = solution.replay.unwrap();
+        assert_eq!(play(&map, &replay), Outcome::Won);
+    }
+
+    #[test]
+    fn standing_still_does_not_win() {
+        let map = map::from_file("assets/levels/1.ron".to_string()).unwrap();
+        let replay = Replay { inputs: vec![(physics::Input::default(), 60)] };
+        assert_eq!(play(&map, &replay), Outcome::Playing);
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use amethyst::{
     ecs::{
         World, WorldExt, Entity, Component, Storage,
@@ -8,8 +9,27 @@ use amethyst::{
     ui::{ UiTransform, UiImage },
 };
 
-use crate::lib::map;
-use crate::lib::utils;
+use crate::map;
+use crate::utils;
+
+// register the components of the game
+pub fn register(world: &mut World) {
+    world.register::<CameraComp>();
+    world.register::<MapComp>();
+    world.register::<PlayerComp>();
+    world.register::<ObjectComp>();
+    world.register::<MovableComp>();
+    world.register::<CollidableComp>();
+    world.register::<InteractableComp>();
+    world.register::<LockComp>();
+    world.register::<SpringComp>();
+    world.register::<DamageComp>();
+    world.register::<EnemyComp>();
+    world.register::<TurretComp>();
+    world.register::<ProjectileComp>();
+    world.register::<SpawnedComp>();
+    world.register::<DescriptionComp>();
+}
 
 // TODO: add a camera component that
 // 1) if can find player: follows the player while within the bounds of the screen
@@ -31,6 +51,7 @@ impl Component for MapComp {
 }
 
 // Player is special
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PlayerComp {
     pub name: String,
     pub can_jump: bool,
@@ -38,6 +59,16 @@ pub struct PlayerComp {
     pub jump_count: i32,
     pub speed: (i32, i32),
     pub last_pos: Option<(i32, i32)>,
+    #[serde(default)]
+    pub inventory: Vec<String>,  // keys held by the player
+    #[serde(default)]
+    pub momentum: i32,  // horizontal speed from launchers, fades by one every tick
+    #[serde(default)]
+    pub health: u32,
+    #[serde(default)]
+    pub max_health: u32,
+    #[serde(default)]
+    pub invulnerable: u32,  // remaining ticks without taking damage
 }
 
 impl Component for PlayerComp {
@@ -45,10 +76,11 @@ impl Component for PlayerComp {
 }
 
 impl PlayerComp {
-    pub fn new(name: String) -> Self {
+    pub fn new(name: String, health: u32) -> Self {
         PlayerComp {
             name, can_jump: false, on_ground: false,
-            jump_count: 0, speed: (0, 0), last_pos: None
+            jump_count: 0, speed: (0, 0), last_pos: None, inventory: vec![], momentum: 0,
+            health, max_health: health, invulnerable: 0,
         }
     }
 
@@ -77,7 +109,7 @@ impl PlayerComp {
 
 // All game objects
 pub enum ObjectType {
-    Player, Target, Coin, Switch, Monster, Elevator, Obstacle
+    Player, Target, Coin, Switch, Monster, Elevator, Obstacle, Checkpoint, Key, Door, Spring, Hazard, Turret, Projectile
 }
 
 pub struct ObjectComp {
@@ -100,6 +132,9 @@ pub struct MovableComp {
     pub name: String,
     pub track: Vec<map::Track>,
     pub speed: (i32, i32),
+    pub update_speed: bool,  // whether reaching a track point changes the speed, a path is paused when false
+    pub path: Option<map::Path>,  // replaces the track
+    pub tick: u32,  // ticks spent on the path
 }
 
 impl Component for MovableComp {
@@ -107,8 +142,8 @@ impl Component for MovableComp {
 }
 
 impl MovableComp {
-    pub fn new(name: String, track: Vec<map::Track>) -> Self {
-        return MovableComp { name, track, speed: (0, 0) }
+    pub fn new(name: String, track: Vec<map::Track>, path: Option<map::Path>) -> Self {
+        return MovableComp { name, track, speed: (0, 0), update_speed: true, path, tick: 0 }
     }
 
     pub fn move_(&mut self,
@@ -120,11 +155,23 @@ impl MovableComp {
                 let x_before_scale = (uitrans.local_x / utils::dpi()) as i32;
                 let y_before_scale = (uitrans.local_y / utils::dpi()) as i32;
 
+                // a path gives the position directly, the speed is only kept for collisions
+                if let Some(path) = &self.path {
+                    if self.update_speed { self.tick += 1; }
+                    let pos = path.position_at(self.tick);
+                    self.speed = (pos.0 - x_before_scale, pos.1 - y_before_scale);
+                    uitrans.local_x = pos.0 as f32 * utils::dpi();
+                    uitrans.local_y = pos.1 as f32 * utils::dpi();
+                    break;
+                }
+
                 // update speed
-                for tr in self.track.iter() {
-                    if tr.pos.0 == x_before_scale && tr.pos.1 == y_before_scale {
-                        self.speed = tr.speed;
-                        break;
+                if self.update_speed {
+                    for tr in self.track.iter() {
+                        if tr.pos.0 == x_before_scale && tr.pos.1 == y_before_scale {
+                            self.speed = tr.speed;
+                            break;
+                        }
                     }
                 }
 
@@ -137,8 +184,115 @@ impl MovableComp {
     }
 }
 
+// Monsters with a behavior instead of a track
+// they fall and collide like the player
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EnemyComp {
+    pub name: String,
+    pub behavior: map::Behavior,
+    pub speed: (i32, i32),
+    pub direction: i32,  // 1 when facing right, -1 when facing left
+    pub on_ground: bool,
+}
+
+impl Component for EnemyComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl EnemyComp {
+    pub fn new(name: String, behavior: map::Behavior) -> Self {
+        EnemyComp { name, behavior, speed: (0, 0), direction: -1, on_ground: false }
+    }
+
+    // choose the speed for this tick from the horizontal distance to the player
+    pub fn think(&mut self, distance: Option<i32>) {
+        let approach = |speed: i32, radius: u32| match distance {
+            Some(dx) if dx.abs() <= radius as i32 && dx.abs() >= speed => Some(dx.signum()),
+            _ => None,
+        };
+        match self.behavior {
+            map::Behavior::Patrol { speed } => {
+                self.speed.0 = self.direction * speed;
+            }
+            map::Behavior::Chase { speed, radius } => {
+                self.speed.0 = 0;
+                if let Some(direction) = approach(speed, radius) {
+                    self.direction = direction;
+                    self.speed.0 = direction * speed;
+                }
+            }
+            map::Behavior::Jump { speed, jump, radius } => {
+                if self.on_ground { self.speed.0 = 0; }
+                if let Some(direction) = approach(speed, radius) {
+                    self.direction = direction;
+                    if self.on_ground {
+                        self.speed = (direction * speed, jump);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Turrets: fire a projectile every interval, optionally only at a player in sight
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TurretComp {
+    pub name: String,
+    pub projectile: map::Projectile,
+    pub interval: u32,
+    pub sight: Option<u32>,
+    pub enabled: bool,
+    pub timer: u32,  // ticks since the last shot
+    pub count: u32,  // projectiles fired, used to name them
+}
+
+impl Component for TurretComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl TurretComp {
+    pub fn new(name: String, projectile: map::Projectile, interval: u32, sight: Option<u32>, enabled: bool) -> Self {
+        TurretComp { name, projectile, interval, sight, enabled, timer: 0, count: 0 }
+    }
+}
+
+// Projectiles: created by turrets while playing and removed when they hit something
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProjectileComp {
+    pub name: String,
+    pub turret: String,  // the turret that fired it, which it passes through
+    pub projectile: map::Projectile,
+    pub lifetime: u32,  // remaining ticks
+}
+
+impl Component for ProjectileComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl ProjectileComp {
+    pub fn new(name: String, turret: String, projectile: map::Projectile, lifetime: u32) -> Self {
+        ProjectileComp { name, turret, projectile, lifetime }
+    }
+}
+
+// Objects created by the spawn command
+pub struct SpawnedComp {
+    pub object: map::Object,  // the definition it was created from
+}
+
+impl Component for SpawnedComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl SpawnedComp {
+    pub fn new(object: map::Object) -> Self {
+        SpawnedComp { object }
+    }
+}
+
 pub struct CollidableComp {
     pub name: String,
+    pub one_way: bool,  // only blocks the player landing from above
 }
 
 impl Component for CollidableComp {
@@ -146,8 +300,60 @@ impl Component for CollidableComp {
 }
 
 impl CollidableComp {
-    pub fn new(name: String) -> Self {
-        CollidableComp { name }
+    pub fn new(name: String, one_way: bool) -> Self {
+        CollidableComp { name, one_way }
+    }
+}
+
+// Keys and doors: a door opens for a key with the same lock
+pub struct LockComp {
+    pub name: String,
+    pub lock: String,
+}
+
+impl Component for LockComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl LockComp {
+    pub fn new(name: String, lock: String) -> Self {
+        LockComp { name, lock }
+    }
+}
+
+// Monsters and hazards: hurt the player on touch
+pub struct DamageComp {
+    pub name: String,
+    pub damage: Option<u32>,  // kills instantly if none
+    pub stompable: bool,  // removed instead when the player lands on it
+}
+
+impl Component for DamageComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl DamageComp {
+    pub fn new(name: String, damage: Option<u32>, stompable: bool) -> Self {
+        DamageComp { name, damage, stompable }
+    }
+}
+
+// Springs: launch the player landing on them
+// the color change runs through the "launch" and "ready" commands of the InteractableComp
+pub struct SpringComp {
+    pub name: String,
+    pub impulse: (i32, i32),
+    pub cooldown: u32,  // ticks before the spring can launch again
+    pub timer: u32,  // remaining cooldown ticks
+}
+
+impl Component for SpringComp {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl SpringComp {
+    pub fn new(name: String, impulse: (i32, i32), cooldown: u32) -> Self {
+        SpringComp { name, impulse, cooldown: cooldown.max(utils::SPRING_MIN_COOLDOWN), timer: 0 }
     }
 }
 
@@ -174,37 +380,29 @@ impl InteractableComp {
         }
     }
 
+    // malformed commands are rejected when the level loads, so this only skips those created at runtime
     fn exec_command(&mut self, world: &mut World, command: &Vec<String>) {
-        if command.len() == 0 { return; }
-        match command[0].as_str() {
-            "state" => {
-                self.state = command[1].clone();
+        match Command::parse(command) {
+            Ok(command) => { self.run(world, &command); }
+            Err(err) => { log::error!("Invalid command {:?} of \"{}\": {}", command, self.name, err); }
+        }
+    }
+
+    fn run(&mut self, world: &mut World, command: &Command) {
+        match command {
+            Command::State(state) => {
+                self.state = state.clone();
             }
-            "color" => {
-                let obj_store = world.read_storage::<ObjectComp>();
-                let mut img_store = world.write_storage::<UiImage>();
-                let new_color = [
-                    command[1].parse::<u32>().unwrap(),
-                    command[2].parse::<u32>().unwrap(),
-                    command[3].parse::<u32>().unwrap(),
-                    255 as u32,
-                ];
-                for (obj, img) in (&obj_store, &mut img_store).join() {
-                    if obj.name == self.name {
-                        if let UiImage::SolidColor(ref mut color) = img {
-                            *color = utils::get_color(new_color);
-                        }
-                        break;
-                    }
-                }
+            Command::Color(color) => {
+                recolor(world, &self.name, *color);
             }
-            "remove" => {
+            Command::Remove(name) => {
                 let obj_store = world.read_storage::<ObjectComp>();
                 let mut entities_to_be_removed: Vec<Entity> = vec![];
                 for entity in (world.entities()).join() {
                     match obj_store.get(entity) {
                         Some(obj) => {
-                            if obj.name == command[1] {
+                            if &obj.name == name {
                                 entities_to_be_removed.push(entity.clone());
                             }
                         }
@@ -216,7 +414,188 @@ impl InteractableComp {
                     world.delete_entity(*ent).expect("Entity does not exist");
                 }
             }
-            _ => {}
+            Command::Pos(name, pos) => {
+                let obj_store = world.read_storage::<ObjectComp>();
+                let mut uitrans_store = world.write_storage::<UiTransform>();
+                for (obj, uitrans) in (&obj_store, &mut uitrans_store).join() {
+                    if &obj.name == name {
+                        uitrans.local_x = pos.0 as f32 * utils::dpi();
+                        uitrans.local_y = pos.1 as f32 * utils::dpi();
+                    }
+                }
+            }
+            Command::Speed(name, speed) => {
+                for movable in (&mut world.write_storage::<MovableComp>()).join() {
+                    if &movable.name == name { movable.speed = *speed; }
+                }
+            }
+            Command::UpdateSpeed(name, update) => {
+                for movable in (&mut world.write_storage::<MovableComp>()).join() {
+                    if &movable.name == name { movable.update_speed = *update; }
+                }
+            }
+            Command::Enabled(name, enabled) => {
+                for turret in (&mut world.write_storage::<TurretComp>()).join() {
+                    if &turret.name == name { turret.enabled = *enabled; }
+                }
+            }
+            Command::Recolor(name, color) => {
+                recolor(world, name, *color);
+            }
+            Command::Spawn(object) => {
+                map::spawn(world, object);
+            }
+            Command::If { name, pos, body } => {
+                if self.check_condition(world, name, *pos) {
+                    for command in body.iter() {
+                        self.run(world, command);
+                    }
+                }
+            }
+        }
+    }
+
+    // currently only supports comparing the position of an object
+    fn check_condition(&self, world: &World, name: &String, pos: (i32, i32)) -> bool {
+        let obj_store = world.read_storage::<ObjectComp>();
+        let uitrans_store = world.read_storage::<UiTransform>();
+        for (obj, uitrans) in (&obj_store, &uitrans_store).join() {
+            if &obj.name == name {
+                return (uitrans.local_x / utils::dpi()) as i32 == pos.0
+                    && (uitrans.local_y / utils::dpi()) as i32 == pos.1;
+            }
+        }
+        false
+    }
+}
+
+// set the color of the object with the name
+fn recolor(world: &mut World, name: &String, [r, g, b]: [u32; 3]) {
+    let obj_store = world.read_storage::<ObjectComp>();
+    let mut img_store = world.write_storage::<UiImage>();
+    for (obj, img) in (&obj_store, &mut img_store).join() {
+        if &obj.name == name {
+            if let UiImage::SolidColor(ref mut color) = img {
+                *color = utils::get_color([r, g, b, 255]);
+            }
+            break;
+        }
+    }
+}
+
+// A command of an interactable
+// levels store commands as lists of strings, which are parsed (and checked when the level loads) into this
+#[derive(Debug, Clone)]
+pub enum Command {
+    State(String),
+    Color([u32; 3]),  // of the interactable itself
+    Remove(String),
+    Pos(String, (i32, i32)),
+    Speed(String, (i32, i32)),
+    UpdateSpeed(String, bool),
+    Enabled(String, bool),
+    Recolor(String, [u32; 3]),
+    Spawn(map::Object),
+    If { name: String, pos: (i32, i32), body: Vec<Command> },  // runs the body if the object is at the position
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "True" => Ok(true),
+        "false" | "False" => Ok(false),
+        _ => Err(format!("\"{}\" is not a bool", value)),
+    }
+}
+
+fn parse_json<T: DeserializeOwned>(value: &str) -> Result<T, String> {
+    serde_json::from_str(value).map_err(|err| format!("\"{}\" is not valid: {}", value, err))
+}
+
+impl Command {
+    pub fn parse(command: &Vec<String>) -> Result<Command, String> {
+        let expect = |len: usize| {
+            if command.len() == len { return Ok(()); }
+            Err(format!("expected {} arguments, found {}", len - 1, command.len() - 1))
+        };
+        match command.get(0).map(|name| name.as_str()) {
+            Some("state") => {
+                expect(2)?;
+                Ok(Command::State(command[1].clone()))
+            }
+            Some("color") => {
+                expect(4)?;
+                let channel = |value: &String| value.parse::<u32>()
+                    .map_err(|err| format!("\"{}\" is not a color channel: {}", value, err));
+                Ok(Command::Color([channel(&command[1])?, channel(&command[2])?, channel(&command[3])?]))
+            }
+            Some("remove") => {
+                expect(2)?;
+                Ok(Command::Remove(command[1].clone()))
+            }
+            Some("object") => {
+                expect(4)?;
+                let (name, value) = (command[1].clone(), command[3].as_str());
+                match command[2].as_str() {
+                    "pos" => Ok(Command::Pos(name, parse_json(value)?)),
+                    "speed" => Ok(Command::Speed(name, parse_json(value)?)),
+                    "update_speed" => Ok(Command::UpdateSpeed(name, parse_bool(value)?)),
+                    "enabled" => Ok(Command::Enabled(name, parse_bool(value)?)),
+                    "color" => Ok(Command::Recolor(name, parse_json(value)?)),
+                    field => Err(format!("unknown field \"{}\"", field)),
+                }
+            }
+            Some("spawn") => {
+                // prefabs are expanded when the map is loaded, so anything but an inline object has an unknown prefab
+                if command.len() == 4 {
+                    return Err(format!("unknown prefab \"{}\"", command[1]));
+                }
+                expect(2)?;
+                let object: map::Object = ron::from_str(command[1].as_str())
+                    .map_err(|err| format!("invalid object: {}", err))?;
+                if let map::Object::Switch(switch) = &object {
+                    for command in switch.command.values().flatten() {
+                        Command::parse(command)?;
+                    }
+                }
+                Ok(Command::Spawn(object))
+            }
+            Some("if") => {
+                // the condition and the body are stored as JSON text
+                expect(3)?;
+                let condition: Vec<String> = parse_json(command[1].as_str())?;
+                let (name, pos) = match Command::parse(&condition)? {
+                    Command::Pos(name, pos) => (name, pos),
+                    _ => { return Err(format!("unsupported condition {:?}", condition)); }
+                };
+                let body: Vec<Vec<String>> = parse_json(command[2].as_str())?;
+                let body = body.iter().map(Command::parse).collect::<Result<Vec<Command>, String>>()?;
+                Ok(Command::If { name, pos, body })
+            }
+            Some(name) => Err(format!("unknown command \"{}\"", name)),
+            None => Err("empty command".to_string()),
+        }
+    }
+
+    // the names of the objects the command refers to, including those in the body of a condition
+    pub fn targets(&self) -> Vec<String> {
+        match self {
+            Command::Remove(name) | Command::Pos(name, _) | Command::Speed(name, _)
+                | Command::UpdateSpeed(name, _) | Command::Enabled(name, _) | Command::Recolor(name, _) => vec![name.clone()],
+            Command::If { name, body, .. } => {
+                let mut names = vec![name.clone()];
+                names.extend(body.iter().flat_map(|command| command.targets()));
+                names
+            }
+            _ => vec![],
+        }
+    }
+
+    // the names of the objects created by the command
+    pub fn spawned(&self) -> Vec<String> {
+        match self {
+            Command::Spawn(object) => vec![object.name().clone()],
+            Command::If { body, .. } => body.iter().flat_map(|command| command.spawned()).collect(),
+            _ => vec![],
         }
     }
 }
@@ -227,3 +606,41 @@ pub struct DescriptionComp;
 impl Component for DescriptionComp {
     type Storage = DenseVecStorage<Self>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(command: &[&str]) -> Result<Command, String> {
+        Command::parse(&command.iter().map(|arg| arg.to_string()).collect())
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert!(matches!(parse(&["state", "open"]), Ok(Command::State(state)) if state == "open"));
+        assert!(matches!(parse(&["color", "1", "2", "3"]), Ok(Command::Color([1, 2, 3]))));
+        assert!(matches!(parse(&["object", "e1", "speed", "[0, -2]"]), Ok(Command::Speed(_, (0, -2)))));
+        assert!(matches!(parse(&["object", "e1", "update_speed", "False"]), Ok(Command::UpdateSpeed(_, false))));
+        assert!(matches!(parse(&["object", "t1", "enabled", "true"]), Ok(Command::Enabled(_, true))));
+        let command = parse(&["if", r#"["object", "e1", "pos", "[1, 2]"]"#, r#"[["remove", "o1"]]"#]).unwrap();
+        assert_eq!(command.targets(), vec!["e1".to_string(), "o1".to_string()]);
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["state"]).is_err());
+        assert!(parse(&["color", "1", "2"]).is_err());
+        assert!(parse(&["color", "1", "2", "red"]).is_err());
+        assert!(parse(&["remove"]).is_err());
+        assert!(parse(&["object", "e1", "speed"]).is_err());
+        assert!(parse(&["object", "e1", "speed", "fast"]).is_err());
+        assert!(parse(&["object", "e1", "update_speed", "yes"]).is_err());
+        assert!(parse(&["object", "e1", "size", "[1, 2]"]).is_err());
+        assert!(parse(&["spawn", "coin", "c1", "[0, 0]"]).is_err());
+        assert!(parse(&["if", "not json", "[]"]).is_err());
+        assert!(parse(&["if", r#"["remove", "o1"]"#, "[]"]).is_err());
+        assert!(parse(&["if", r#"["object", "e1", "pos", "[1, 2]"]"#, r#"[["state"]]"#]).is_err());
+        assert!(parse(&["jump"]).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::map;
+
+// Differences between two versions of a level, by object name
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "kebab-case")]
+pub enum Change {
+    Added { object: String, kind: String },
+    Removed { object: String, kind: String },
+    Moved { object: String, from: Value, to: Value },
+    Resized { object: String, from: Value, to: Value },
+    Recolored { object: String, from: Value, to: Value },
+    TrackChanged { object: String, field: String, from: Value, to: Value },  // the track or the path
+    CommandsChanged { object: String, event: String, removed: Vec<Vec<String>>, added: Vec<Vec<String>> },
+    Changed { object: String, field: String, from: Value, to: Value },  // any other setting
+}
+
+// The result of comparing two level files
+#[derive(Debug, Serialize)]
+pub struct Diff {
+    pub old: String,
+    pub new: String,
+    pub changes: Vec<Change>,
+}
+
+// the number of waypoints of a track or a path
+fn waypoints(value: &Value) -> usize {
+    match value {
+        Value::Array(track) => track.len(),
+        Value::Object(path) => path.get("waypoints").and_then(|wps| wps.as_array()).map_or(0, |wps| wps.len()),
+        _ => 0,
+    }
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Change::Added { object, kind } => write!(f, "+ {} ({}) added", object, kind),
+            Change::Removed { object, kind } => write!(f, "- {} ({}) removed", object, kind),
+            Change::Moved { object, from, to } => write!(f, "~ {} moved from {} to {}", object, from, to),
+            Change::Resized { object, from, to } => write!(f, "~ {} resized from {} to {}", object, from, to),
+            Change::Recolored { object, from, to } => write!(f, "~ {} recolored from {} to {}", object, from, to),
+            Change::TrackChanged { object, field, from, to } => write!(f, "~ {} {} changed ({} -> {} waypoints)",
+                object, field, waypoints(from), waypoints(to)),
+            Change::CommandsChanged { object, event, removed, added } => {
+                write!(f, "~ {} commands on \"{}\" changed", object, event)?;
+                for command in removed.iter() {
+                    write!(f, "\n    - {}", serde_json::to_string(command).unwrap())?;
+                }
+                for command in added.iter() {
+                    write!(f, "\n    + {}", serde_json::to_string(command).unwrap())?;
+                }
+                Ok(())
+            }
+            Change::Changed { object, field, from, to } => write!(f, "~ {} {} changed from {} to {}", object, field, from, to),
+        }
+    }
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "--- {}", self.old)?;
+        writeln!(f, "+++ {}", self.new)?;
+        if self.changes.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+// load both levels and compare them
+pub fn diff_files(old: String, new: String) -> Result<Diff, Box<dyn std::error::Error>> {
+    let changes = diff_maps(&map::from_file(old.clone())?, &map::from_file(new.clone())?);
+    Ok(Diff { old, new, changes })
+}
+
+// every object of a level with its kind and its settings
+// objects that share a name are told apart by their order, the second "c" is reported as "c#2"
+fn entries(map: &map::Map) -> BTreeMap<String, (String, Value)> {
+    let mut entries = BTreeMap::new();
+    let mut insert = |name: &String, entry: (String, Value)| {
+        let (mut key, mut count) = (name.clone(), 1);
+        while entries.contains_key(&key) {
+            count += 1;
+            key = format!("{}#{}", name, count);
+        }
+        entries.insert(key, entry);
+    };
+    insert(&"player".to_string(), ("Player".to_string(), serde_json::to_value(&map.player).unwrap()));
+    for obj in map.descriptions.iter() {
+        insert(&obj.name, ("Description".to_string(), serde_json::to_value(obj).unwrap()));
+    }
+    for obj in map.objects().iter() {
+        // objects are serialized as { <kind>: { <settings> } }
+        if let Value::Object(tagged) = serde_json::to_value(obj).unwrap() {
+            if let Some((kind, settings)) = tagged.into_iter().next() {
+                insert(obj.name(), (kind, settings));
+            }
+        }
+    }
+    entries
+}
+
+pub fn diff_maps(old: &map::Map, new: &map::Map) -> Vec<Change> {
+    let mut changes = vec![];
+
+    // settings of the level itself
+    let settings = |map: &map::Map| vec![
+        ("size", serde_json::to_value(map.size).unwrap()),
+        ("lives", serde_json::to_value(map.lives).unwrap()),
+        ("rewind", serde_json::to_value(&map.rewind).unwrap()),
+    ];
+    for ((field, from), (_, to)) in settings(old).into_iter().zip(settings(new).into_iter()) {
+        if from != to {
+            changes.push(Change::Changed { object: "map".to_string(), field: field.to_string(), from, to });
+        }
+    }
+
+    let (old_entries, new_entries) = (entries(old), entries(new));
+    let names: BTreeSet<&String> = old_entries.keys().chain(new_entries.keys()).collect();
+    for name in names {
+        match (old_entries.get(name), new_entries.get(name)) {
+            (Some((kind, _)), None) => changes.push(Change::Removed { object: name.clone(), kind: kind.clone() }),
+            (None, Some((kind, _))) => changes.push(Change::Added { object: name.clone(), kind: kind.clone() }),
+            // an object that changes its kind is a different object with the same name
+            (Some((old_kind, _)), Some((new_kind, _))) if old_kind != new_kind => {
+                changes.push(Change::Removed { object: name.clone(), kind: old_kind.clone() });
+                changes.push(Change::Added { object: name.clone(), kind: new_kind.clone() });
+            }
+            (Some((_, from)), Some((_, to))) => diff_settings(name, from, to, &mut changes),
+            (None, None) => {}
+        }
+    }
+    changes
+}
+
+fn diff_settings(name: &String, from: &Value, to: &Value, changes: &mut Vec<Change>) {
+    let empty = serde_json::Map::new();
+    let (from, to) = (from.as_object().unwrap_or(&empty), to.as_object().unwrap_or(&empty));
+    let fields: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    for field in fields {
+        let (old_value, new_value) = (from.get(field).unwrap_or(&Value::Null), to.get(field).unwrap_or(&Value::Null));
+        if old_value == new_value { continue; }
+        let (object, old_value, new_value) = (name.clone(), old_value.clone(), new_value.clone());
+        let change = match field.as_str() {
+            "pos" => Change::Moved { object, from: old_value, to: new_value },
+            "size" => Change::Resized { object, from: old_value, to: new_value },
+            "color" => Change::Recolored { object, from: old_value, to: new_value },
+            "track" | "path" => Change::TrackChanged { object, field: field.clone(), from: old_value, to: new_value },
+            "command" => {
+                diff_commands(name, &old_value, &new_value, changes);
+                continue;
+            }
+            _ => Change::Changed { object, field: field.clone(), from: old_value, to: new_value },
+        };
+        changes.push(change);
+    }
+}
+
+// the commands removed from and added to each event of a switch
+fn diff_commands(name: &String, from: &Value, to: &Value, changes: &mut Vec<Change>) {
+    let parse = |value: &Value| serde_json::from_value::<HashMap<String, Vec<Vec<String>>>>(value.clone())
+        .unwrap_or_default();
+    let (from, to) = (parse(from), parse(to));
+    let events: BTreeSet<&String> = from.keys().chain(to.keys()).collect();
+    for event in events {
+        let old_commands = from.get(event).cloned().unwrap_or_default();
+        let new_commands = to.get(event).cloned().unwrap_or_default();
+        if old_commands == new_commands { continue; }
+        // commands that are only reordered are reported with nothing removed or added
+        let removed: Vec<Vec<String>> = old_commands.iter().filter(|cmd| !new_commands.contains(cmd)).cloned().collect();
+        let added: Vec<Vec<String>> = new_commands.iter().filter(|cmd| !old_commands.contains(cmd)).cloned().collect();
+        changes.push(Change::CommandsChanged { object: name.clone(), event: event.clone(), removed, added });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn coin(name: &str, pos: (i32, i32)) -> map::Object {
+        map::Object::Coin(map::Coin { name: name.to_string(), pos, size: (10, 10), color: (255, 255, 0) })
+    }
+
+    fn diff(old: &map::Map, new: &map::Map) -> Diff {
+        Diff { old: "old.ron".to_string(), new: "new.ron".to_string(), changes: diff_maps(old, new) }
+    }
+
+    #[test]
+    fn changes_are_printed() {
+        let mut old = map::Map::new((200, 100));
+        old.add(coin("a", (0, 0)));
+        old.add(coin("b", (0, 0)));
+        let mut new = map::Map::new((200, 100));
+        new.add(coin("a", (20, 10)));
+        new.add(coin("c", (0, 0)));
+        assert_eq!(diff(&old, &new).to_string(), "--- old.ron\n+++ new.ron\n\
+            ~ a moved from [0,0] to [20,10]\n\
+            - b (Coin) removed\n\
+            + c (Coin) added\n");
+        assert_eq!(diff(&old, &old).to_string(), "--- old.ron\n+++ new.ron\nno changes\n");
+    }
+
+    #[test]
+    fn changes_are_serialized() {
+        let mut old = map::Map::new((200, 100));
+        old.add(coin("a", (0, 0)));
+        let mut new = map::Map::new((300, 100));
+        new.add(coin("a", (0, 5)));
+        assert_eq!(serde_json::to_value(diff(&old, &new)).unwrap(), json!({
+            "old": "old.ron",
+            "new": "new.ron",
+            "changes": [
+                { "change": "changed", "object": "map", "field": "size", "from": [200, 100], "to": [300, 100] },
+                { "change": "moved", "object": "a", "from": [0, 0], "to": [0, 5] },
+            ],
+        }));
+    }
+
+    #[test]
+    fn duplicate_names_are_told_apart() {
+        let mut old = map::Map::new((200, 100));
+        old.add(coin("c", (0, 0)));
+        old.add(coin("c", (10, 0)));
+        let mut new = map::Map::new((200, 100));
+        new.add(coin("c", (0, 0)));
+        new.add(coin("c", (10, 5)));
+        new.add(coin("c", (20, 0)));
+        assert_eq!(diff_maps(&old, &new), vec![
+            Change::Moved { object: "c#2".to_string(), from: json!([10, 0]), to: json!([10, 5]) },
+            Change::Added { object: "c#3".to_string(), kind: "Coin".to_string() },
+        ]);
+    }
+}
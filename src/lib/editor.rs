@@ -0,0 +1,747 @@
+use std::collections::HashMap;
+use amethyst::{
+    core::Parent,
+    ecs::{ WorldExt, Join, Entity },
+    input::{ InputHandler, StringBindings, InputEvent, VirtualKeyCode, is_key_down },
+    prelude::*,
+    ui::{ Anchor, UiImage, UiTransform },
+    window::ScreenDimensions,
+    winit::{ Event, MouseButton, WindowEvent },
+};
+
+use crate::components;
+use crate::entities;
+use crate::levels;
+use crate::map;
+use crate::states;
+use crate::utils;
+
+// The kinds of objects that can be placed
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Target, Coin, Switch, Monster, Elevator, Obstacle, Checkpoint,
+    Key, Door, Spring, Hazard, Turret, Description,
+}
+
+const KINDS: [Kind; 13] = [
+    Kind::Target, Kind::Coin, Kind::Switch, Kind::Monster, Kind::Elevator, Kind::Obstacle, Kind::Checkpoint,
+    Kind::Key, Kind::Door, Kind::Spring, Kind::Hazard, Kind::Turret, Kind::Description,
+];
+
+impl Kind {
+    fn label(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    // add a new object with default settings to the map
+    fn create(&self, map: &mut map::Map, name: String, pos: (i32, i32)) {
+        let track = vec![map::Track { pos, speed: (0, 0) }];
+        let object = match self {
+            Kind::Target => map::Object::Target(map::Target {
+                name, pos, size: (40, 60), color: (255, 215, 0),
+            }),
+            Kind::Coin => map::Object::Coin(map::Coin {
+                name, pos, size: (20, 20), color: (255, 215, 0),
+            }),
+            Kind::Switch => map::Object::Switch(map::Switch {
+                name, pos, size: (30, 30), color: (0, 0, 192), command: HashMap::new(),
+            }),
+            Kind::Monster => map::Object::Monster(map::Monster {
+                name, track, path: None, size: (40, 40), color: (192, 0, 0),
+                damage: None, stompable: false, behavior: None,
+            }),
+            Kind::Elevator => map::Object::Elevator(map::Elevator {
+                name, track, path: None, size: (100, 20), color: (128, 64, 0), one_way: false,
+            }),
+            Kind::Obstacle => map::Object::Obstacle(map::Obstacle {
+                name, pos, size: (200, 40), color: (96, 96, 96), one_way: false,
+            }),
+            Kind::Checkpoint => map::Object::Checkpoint(map::Checkpoint {
+                name, pos, size: (30, 60), color: (128, 128, 128), active_color: (0, 192, 0),
+            }),
+            Kind::Key => map::Object::Key(map::Key {
+                name, id: "".to_string(), pos, size: (20, 20), color: (0, 128, 255),
+            }),
+            Kind::Door => map::Object::Door(map::Door {
+                name, id: "".to_string(), pos, size: (30, 100), color: (0, 128, 255),
+            }),
+            Kind::Spring => map::Object::Spring(map::Spring {
+                name, pos, size: (40, 20), color: (0, 160, 160), impulse: (0, 40), cooldown: 0, active_color: None,
+            }),
+            Kind::Hazard => map::Object::Hazard(map::Hazard {
+                name, pos, size: (60, 20), color: (255, 64, 0), damage: None,
+            }),
+            Kind::Turret => map::Object::Turret(map::Turret {
+                name, pos, size: (40, 40), color: (64, 64, 64), interval: 60, sight: None, enabled: true,
+                projectile: map::Projectile {
+                    size: (10, 10), color: (0, 0, 0), speed: (-6, 0), lifetime: 120, damage: None,
+                },
+            }),
+            Kind::Description => {
+                map.descriptions.push(map::Description {
+                    name, pos, size: (200, 40), text: "Text".to_string(),
+                    font: map::Font { family: "merriweather.ttf".to_string(), size: 20 }, color: (0, 0, 0),
+                });
+                return;
+            }
+        };
+        map.add(object);
+    }
+}
+
+// The object being edited
+#[derive(Debug, Clone, PartialEq)]
+enum Selection {
+    Player,
+    Object(String),
+    Description(String),
+}
+
+impl Selection {
+    fn name(&self) -> String {
+        match self {
+            Selection::Player => "player".to_string(),
+            Selection::Object(name) | Selection::Description(name) => name.clone(),
+        }
+    }
+
+    // position and size as stored in the map
+    fn rect(&self, map: &map::Map) -> Option<((i32, i32), (u32, u32))> {
+        match self {
+            Selection::Player => Some((map.player.pos, map.player.size)),
+            Selection::Object(name) => map.objects().into_iter()
+                .find(|obj| obj.name() == name)
+                .map(|obj| (obj.pos(), obj.style().0)),
+            Selection::Description(name) => map.descriptions.iter()
+                .find(|obj| obj.name == *name)
+                .map(|obj| (obj.pos, (obj.size.0.max(0) as u32, obj.size.1.max(0) as u32))),
+        }
+    }
+
+    // the top left corner, descriptions are positioned by their center
+    fn corner(&self, pos: (i32, i32), size: (u32, u32)) -> (i32, i32) {
+        match self {
+            Selection::Description(_) => (pos.0 - size.0 as i32 / 2, pos.1 + size.1 as i32 / 2),
+            _ => pos,
+        }
+    }
+
+    fn color(&self, map: &map::Map) -> Option<(u32, u32, u32)> {
+        match self {
+            Selection::Player => Some(map.player.color),
+            Selection::Object(name) => map.objects().into_iter()
+                .find(|obj| obj.name() == name)
+                .map(|obj| obj.style().1),
+            Selection::Description(name) => map.descriptions.iter()
+                .find(|obj| obj.name == *name)
+                .map(|obj| obj.color),
+        }
+    }
+
+    fn fields(&self, map: &map::Map) -> Vec<Field> {
+        match self {
+            Selection::Player => vec![Field::Color],
+            Selection::Object(name) => match map.switches.iter().any(|obj| obj.name == *name) {
+                true => vec![Field::Name, Field::Color, Field::Commands],
+                false => vec![Field::Name, Field::Color],
+            },
+            Selection::Description(_) => vec![Field::Name, Field::Color],
+        }
+    }
+
+    // move and resize
+    fn set_rect(&self, map: &mut map::Map, pos: (i32, i32), size: (u32, u32)) {
+        match self {
+            Selection::Player => {
+                map.player.pos = pos;
+                map.player.size = size;
+            }
+            Selection::Object(name) => {
+                if let Some(obj) = map.objects().into_iter().find(|obj| obj.name() == name) {
+                    let color = obj.style().1;
+                    map.replace(name, obj.placed(name.clone(), pos).restyled(size, color));
+                }
+            }
+            Selection::Description(name) => {
+                if let Some(obj) = map.descriptions.iter_mut().find(|obj| obj.name == *name) {
+                    obj.pos = pos;
+                    obj.size = (size.0 as i32, size.1 as i32);
+                }
+            }
+        }
+    }
+
+    fn set_color(&self, map: &mut map::Map, color: (u32, u32, u32)) {
+        match self {
+            Selection::Player => { map.player.color = color; }
+            Selection::Object(name) => {
+                if let Some(obj) = map.objects().into_iter().find(|obj| obj.name() == name) {
+                    let size = obj.style().0;
+                    map.replace(name, obj.restyled(size, color));
+                }
+            }
+            Selection::Description(name) => {
+                if let Some(obj) = map.descriptions.iter_mut().find(|obj| obj.name == *name) {
+                    obj.color = color;
+                }
+            }
+        }
+    }
+
+    // returns the renamed selection
+    fn rename(&self, map: &mut map::Map, new_name: String) -> Selection {
+        match self {
+            Selection::Player => Selection::Player,
+            Selection::Object(name) => {
+                if let Some(obj) = map.objects().into_iter().find(|obj| obj.name() == name) {
+                    let pos = obj.pos();
+                    map.replace(name, obj.placed(new_name.clone(), pos));
+                    map.rename_references(name, &new_name);
+                }
+                Selection::Object(new_name)
+            }
+            Selection::Description(name) => {
+                if let Some(obj) = map.descriptions.iter_mut().find(|obj| obj.name == *name) {
+                    obj.name = new_name.clone();
+                }
+                Selection::Description(new_name)
+            }
+        }
+    }
+}
+
+// The fields of the side panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Name,
+    Color,  // "r g b"
+    Commands,  // the commands of a switch in JSON
+}
+
+// A move or resize with the mouse, applied to the map when the button is released
+struct Drag {
+    resize: bool,  // dragging the bottom right corner
+    start: (f32, f32),  // mouse position on the map when the drag started
+    orig: ((i32, i32), (u32, u32)),  // position and size before the drag
+    rect: ((i32, i32), (u32, u32)),  // position and size being dragged to
+}
+
+// Editor: place, move, resize and delete the objects of a level
+pub struct EditorState {
+    level: levels::Level,  // where the level is saved, also played when play-testing
+    map: map::Map,  // the level with all unsaved changes
+    offset: (f32, f32),  // position of the map on the screen, panned with the arrow keys
+    kind: Option<Kind>,  // the kind of object placed by the next click on the map
+    selected: Option<Selection>,
+    drag: Option<Drag>,
+    editing: Option<(Field, String)>,  // the field being typed into and its text
+    status: String,  // the result of the last action
+    b_kinds: Vec<(Kind, entities::Button)>,  // button data (not the actual entities)
+    b_fields: Vec<(Field, entities::Button)>,
+    b_save: Option<entities::Button>,
+    b_play: Option<entities::Button>,
+    b_menu: Option<entities::Button>,
+}
+
+impl EditorState {
+    pub fn new(level: levels::Level) -> Self {
+        let map = if std::path::Path::new(&level.path).is_file() {
+            level.load_map().unwrap_or_else(|err| {
+                log::error!("Failed to load level \"{}\": {}", level.path, err);
+                map::Map::new((2560, 720))
+            })
+        } else {
+            map::Map::new((2560, 720))
+        };
+        let status = match save_error(&level, &map) {
+            Some(err) => format!("Read-only: {}", err),
+            None => "".to_string(),
+        };
+        EditorState {
+            level, map, offset: (0., 0.), kind: None, selected: None, drag: None, editing: None,
+            status, b_kinds: vec![], b_fields: vec![], b_save: None, b_play: None, b_menu: None,
+        }
+    }
+
+    // instantiate the map, the selection and the side panel
+    fn build(&mut self, world: &mut World) {
+        world.delete_all();
+        self.map.initialize(world, &self.level.path);
+        if let Some(map_ent) = map::find_map_entity(world) {
+            if let Some(uitrans) = world.write_storage::<UiTransform>().get_mut(map_ent) {
+                uitrans.local_x = self.offset.0;
+                uitrans.local_y = self.offset.1;
+            }
+            if let Some(rect) = self.selected.as_ref().and_then(|sel| sel.rect(&self.map)) {
+                self.build_selection(world, map_ent, rect);
+            }
+        }
+        self.build_panel(world);
+    }
+
+    // a frame around the selection and a handle for resizing it
+    fn build_selection(&self, world: &mut World, map_ent: Entity, (pos, size): ((i32, i32), (u32, u32))) {
+        let corner = self.selected.as_ref().unwrap().corner(pos, size);
+        world.create_entity()
+            .with(UiTransform::new(
+                "editor-frame".to_string(), Anchor::BottomLeft, Anchor::TopLeft,
+                (corner.0 - 3) as f32 * utils::dpi(), (corner.1 + 3) as f32 * utils::dpi(), 0.05,
+                (size.0 + 6) as f32 * utils::dpi(), (size.1 + 6) as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(utils::get_color(utils::RED)))
+            .with(Parent::new(map_ent))
+            .build();
+        world.create_entity()
+            .with(UiTransform::new(
+                "editor-handle".to_string(), Anchor::BottomLeft, Anchor::TopLeft,
+                (corner.0 + size.0 as i32 - HANDLE) as f32 * utils::dpi(),
+                (corner.1 - size.1 as i32 + HANDLE) as f32 * utils::dpi(), 0.9,
+                HANDLE as f32 * utils::dpi(), HANDLE as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(utils::get_color(utils::RED)))
+            .with(Parent::new(map_ent))
+            .build();
+    }
+
+    fn build_panel(&mut self, world: &mut World) {
+        let dimensions = (*world.read_resource::<ScreenDimensions>()).clone();
+        let x = dimensions.width() / utils::dpi() * 0.5 - PANEL_WIDTH * 0.5;
+        world.create_entity()
+            .with(UiTransform::new(
+                "editor-panel".to_string(), Anchor::Middle, Anchor::Middle,
+                x * utils::dpi(), 0., 1.,
+                PANEL_WIDTH * utils::dpi(), dimensions.height()))
+            .with(UiImage::SolidColor(utils::get_color(utils::WHITE)))
+            .build();
+
+        // object kinds in two columns, the active one is darker
+        self.b_kinds = vec![];
+        for (index, kind) in KINDS.iter().enumerate() {
+            let bg_color = if self.kind == Some(*kind) { utils::GRAY_3 } else { utils::GRAY_1 };
+            let mut button = entities::Button::new(
+                kind.label(), 135., 28., utils::get_color(utils::BLACK), utils::get_color(bg_color),
+                utils::get_color(utils::GRAY_2), "merriweather.ttf".to_string(), 16.);
+            let (col, row) = ((index % 2) as f32, (index / 2) as f32);
+            button.instantiate(format!("editor-kind-{}", index), world, x + (col - 0.5) * 142., 320. - row * 34., 2.);
+            self.b_kinds.push((*kind, button));
+        }
+
+        // the fields of the selection
+        let title = self.selected.as_ref().map_or("Nothing selected".to_string(), |sel| sel.name());
+        entities::Label::default(title, PANEL_WIDTH - 20., 30., "merriweather-b.ttf".to_string(), 18.)
+            .instantiate("editor-selected".to_string(), world, x, 75., 2.);
+        self.b_fields = vec![];
+        let fields = self.selected.as_ref().map_or(vec![], |sel| sel.fields(&self.map));
+        for (index, field) in fields.into_iter().enumerate() {
+            let text = match &self.editing {
+                // show the end of the text being typed
+                Some((editing, text)) if *editing == field => {
+                    let start = text.chars().count().saturating_sub(FIELD_CHARS);
+                    text.chars().skip(start).collect::<String>() + "_"
+                }
+                _ => {
+                    let text = self.field_text(field);
+                    match text.chars().count() > FIELD_CHARS {
+                        true => text.chars().take(FIELD_CHARS).collect::<String>() + "...",
+                        false => text,
+                    }
+                }
+            };
+            let mut button = entities::Button::default(
+                format!("{:?}: {}", field, text), PANEL_WIDTH - 20., 30., "merriweather.ttf".to_string(), 14.);
+            button.instantiate(format!("editor-field-{}", index), world, x, 40. - index as f32 * 36., 2.);
+            self.b_fields.push((field, button));
+        }
+
+        // help and status
+        let lines = [
+            "Click a kind, then the map to place it",
+            "Drag to move, drag the corner to resize",
+            "Del: delete, arrows: pan, P: play-test",
+            self.status.as_str(),
+        ];
+        for (index, line) in lines.iter().enumerate() {
+            entities::Label::default(line.to_string(), PANEL_WIDTH - 20., 24., "merriweather.ttf".to_string(), 13.)
+                .instantiate(format!("editor-help-{}", index), world, x, -90. - index as f32 * 22., 2.);
+        }
+
+        // actions
+        self.b_save = Some(entities::Button::default(
+            "Save".to_string(), PANEL_WIDTH - 20., 36., "merriweather.ttf".to_string(), 20.));
+        self.b_save.as_mut().unwrap().instantiate("editor-save".to_string(), world, x, -210., 2.);
+        self.b_play = Some(entities::Button::default(
+            "Play-test".to_string(), PANEL_WIDTH - 20., 36., "merriweather.ttf".to_string(), 20.));
+        self.b_play.as_mut().unwrap().instantiate("editor-play".to_string(), world, x, -260., 2.);
+        self.b_menu = Some(entities::Button::default(
+            "Exit to menu".to_string(), PANEL_WIDTH - 20., 36., "merriweather.ttf".to_string(), 20.));
+        self.b_menu.as_mut().unwrap().instantiate("editor-menu".to_string(), world, x, -310., 2.);
+    }
+
+    fn field_text(&self, field: Field) -> String {
+        let selected = match &self.selected {
+            Some(selected) => selected,
+            None => { return "".to_string(); }
+        };
+        match field {
+            Field::Name => selected.name(),
+            Field::Color => selected.color(&self.map)
+                .map_or("".to_string(), |color| format!("{} {} {}", color.0, color.1, color.2)),
+            Field::Commands => self.map.switches.iter()
+                .find(|obj| obj.name == selected.name())
+                .map_or("".to_string(), |obj| serde_json::to_string(&obj.command).unwrap()),
+        }
+    }
+
+    // apply the text typed into a field
+    fn commit(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let (field, text) = match self.editing.take() {
+            Some(editing) => editing,
+            None => { return Ok(()); }
+        };
+        let selected = match &self.selected {
+            Some(selected) => selected.clone(),
+            None => { return Ok(()); }
+        };
+        match field {
+            Field::Name => {
+                let text = text.trim().to_string();
+                if text == selected.name() { return Ok(()); }
+                if text.is_empty() || self.names().contains(&text) {
+                    return Err(format!("the name \"{}\" is empty or already used", text).into());
+                }
+                self.selected = Some(selected.rename(&mut self.map, text));
+            }
+            Field::Color => {
+                let values: Vec<u32> = text.split(|c: char| !c.is_ascii_digit())
+                    .filter(|value| !value.is_empty())
+                    .map(|value| value.parse::<u32>())
+                    .collect::<Result<_, _>>()?;
+                if values.len() != 3 || values.iter().any(|value| *value > 255) {
+                    return Err(format!("\"{}\" is not a color, use \"r g b\"", text).into());
+                }
+                selected.set_color(&mut self.map, (values[0], values[1], values[2]));
+            }
+            Field::Commands => {
+                let command: HashMap<String, Vec<Vec<String>>> = serde_json::from_str(text.as_str())?;
+                for command in command.values().flatten() {
+                    components::Command::parse(command).map_err(|err| format!("command {:?}: {}", command, err))?;
+                }
+                if let Some(obj) = self.map.switches.iter_mut().find(|obj| obj.name == selected.name()) {
+                    obj.command = command;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names = self.map.object_names();
+        names.push("player".to_string());
+        names.extend(self.map.descriptions.iter().map(|obj| obj.name.clone()));
+        names
+    }
+
+    // the topmost object at a position on the map
+    fn pick(&self, pos: (f32, f32)) -> Option<Selection> {
+        let mut candidates = vec![Selection::Player];
+        candidates.extend(self.map.objects().iter().rev().map(|obj| Selection::Object(obj.name().clone())));
+        candidates.extend(self.map.descriptions.iter().map(|obj| Selection::Description(obj.name.clone())));
+        candidates.into_iter().find(|sel| match sel.rect(&self.map) {
+            Some((obj_pos, size)) => {
+                let corner = sel.corner(obj_pos, size);
+                pos.0 >= corner.0 as f32 && pos.0 <= (corner.0 + size.0 as i32) as f32
+                    && pos.1 <= corner.1 as f32 && pos.1 >= (corner.1 - size.1 as i32) as f32
+            }
+            None => false,
+        })
+    }
+
+    // whether a position on the map is on the resize handle of the selection
+    fn on_handle(&self, pos: (f32, f32)) -> bool {
+        let selected = match &self.selected {
+            Some(selected) => selected,
+            None => { return false; }
+        };
+        match selected.rect(&self.map) {
+            Some((obj_pos, size)) => {
+                let corner = selected.corner(obj_pos, size);
+                let (x, y) = (corner.0 + size.0 as i32, corner.1 - size.1 as i32);
+                pos.0 >= (x - HANDLE) as f32 && pos.0 <= (x + HANDLE) as f32
+                    && pos.1 >= (y - HANDLE) as f32 && pos.1 <= (y + HANDLE) as f32
+            }
+            None => false,
+        }
+    }
+
+    // unscaled position on the map from the mouse position
+    fn map_pos(&self, world: &World, (x, y): (f32, f32)) -> (f32, f32) {
+        let h = world.read_resource::<ScreenDimensions>().height();
+        ((x - self.offset.0) / utils::dpi(), (h - y - self.offset.1) / utils::dpi())
+    }
+
+    // show the dragged position without rebuilding the map
+    fn update_drag(&self, world: &mut World) {
+        let (drag, selected) = match (&self.drag, &self.selected) {
+            (Some(drag), Some(selected)) => (drag, selected),
+            _ => { return; }
+        };
+        let map_ent = match map::find_map_entity(world) {
+            Some(ent) => ent,
+            None => { return; }
+        };
+        let (pos, size) = drag.rect;
+        let corner = selected.corner(pos, size);
+        let parent_store = world.read_storage::<Parent>();
+        let mut uitrans_store = world.write_storage::<UiTransform>();
+        for (uitrans, parent) in (&mut uitrans_store, &parent_store).join() {
+            if parent.entity != map_ent { continue; }
+            let (x, y, w, h) = if uitrans.id == selected.name() {
+                (pos.0, pos.1, size.0, size.1)
+            } else if uitrans.id == "editor-frame" {
+                (corner.0 - 3, corner.1 + 3, size.0 + 6, size.1 + 6)
+            } else if uitrans.id == "editor-handle" {
+                (corner.0 + size.0 as i32 - HANDLE, corner.1 - size.1 as i32 + HANDLE, HANDLE as u32, HANDLE as u32)
+            } else {
+                continue;
+            };
+            uitrans.local_x = x as f32 * utils::dpi();
+            uitrans.local_y = y as f32 * utils::dpi();
+            uitrans.width = w as f32 * utils::dpi();
+            uitrans.height = h as f32 * utils::dpi();
+        }
+    }
+
+    fn save(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(err) = save_error(&self.level, &self.map) {
+            return Err(err.into());
+        }
+        std::fs::create_dir_all(&self.level.pack.dir)?;
+        self.map.to_file(&self.level.path)?;
+        let manifest_path = self.level.pack.dir.join("manifest.ron").into_os_string().into_string().unwrap();
+        if !std::path::Path::new(&manifest_path).is_file() {
+            let manifest = levels::Manifest {
+                name: Some(self.level.pack.name.clone()), fonts: HashMap::new(), strict_tracks: false,
+                levels: vec![self.level.info.clone()],
+            };
+            manifest.to_file(manifest_path)?;
+        }
+        Ok(())
+    }
+
+    // clicks on the side panel, returns None if no button was clicked
+    fn click_panel(&mut self, world: &mut World, x: f32, y: f32) -> Option<SimpleTrans> {
+        if let Some((kind, _)) = self.b_kinds.iter().find(|(_, btn)| btn.in_range(x, y)) {
+            self.kind = if self.kind == Some(*kind) { None } else { Some(*kind) };
+            return Some(Trans::None);
+        }
+        if let Some((field, _)) = self.b_fields.iter().find(|(_, btn)| btn.in_range(x, y)) {
+            let field = *field;
+            if let Err(err) = self.commit() {
+                self.status = err.to_string();
+            }
+            self.editing = Some((field, self.field_text(field)));
+            return Some(Trans::None);
+        }
+        if self.b_save.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            self.status = match self.save() {
+                Ok(()) => format!("Saved to {}", self.level.path),
+                Err(err) => {
+                    log::error!("Failed to save level \"{}\": {}", self.level.path, err);
+                    err.to_string()
+                }
+            };
+            return Some(Trans::None);
+        }
+        if self.b_play.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            return Some(self.play_test(world));
+        }
+        if self.b_menu.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            world.delete_all();
+            return Some(Trans::Replace(Box::new(states::MenuState::default())));
+        }
+        None
+    }
+
+    // clicks on the map place an object of the chosen kind or start dragging
+    fn click_map(&mut self, pos: (f32, f32)) {
+        if let Err(err) = self.commit() {
+            self.status = err.to_string();
+        }
+        let snapped = (snap(pos.0), snap(pos.1));
+
+        if let Some(kind) = self.kind.take() {
+            let names = self.names();
+            let prefix = kind.label().to_lowercase();
+            let name = (0..).map(|i| format!("{}-{}", prefix, i)).find(|name| !names.contains(name)).unwrap();
+            kind.create(&mut self.map, name.clone(), snapped);
+            self.selected = Some(match kind {
+                Kind::Description => Selection::Description(name),
+                _ => Selection::Object(name),
+            });
+            return;
+        }
+
+        let resize = self.on_handle(pos);
+        if !resize {
+            self.selected = self.pick(pos);
+        }
+        self.drag = self.selected.as_ref().and_then(|sel| sel.rect(&self.map))
+            .map(|rect| Drag { resize, start: pos, orig: rect, rect });
+    }
+
+    fn play_test(&mut self, world: &mut World) -> SimpleTrans {
+        if let Err(err) = self.commit() {
+            self.status = err.to_string();
+        }
+        self.drag = None;
+        world.delete_all();
+        Trans::Push(Box::new(states::GameState::play_test(self.level.clone(), self.map.clone())))
+    }
+
+    // typing into a field, enter applies the text
+    fn type_char(&mut self, c: char) {
+        let text = match &mut self.editing {
+            Some((_, text)) => text,
+            None => { return; }
+        };
+        match c {
+            '\r' | '\n' => {
+                if let Err(err) = self.commit() {
+                    self.status = err.to_string();
+                }
+            }
+            '\u{8}' => { text.pop(); }
+            c if !c.is_control() => { text.push(c); }
+            _ => {}
+        }
+    }
+}
+
+// size of the resize handle in unscaled pixels
+const HANDLE: i32 = 8;
+// objects are moved and resized in steps of this many unscaled pixels
+const GRID: i32 = 10;
+const PANEL_WIDTH: f32 = 300.;
+const FIELD_CHARS: usize = 28;
+const PAN_STEP: f32 = 200.;
+
+fn snap(value: f32) -> i32 {
+    (value / GRID as f32).round() as i32 * GRID
+}
+
+impl SimpleState for EditorState {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.build(data.world);
+    }
+
+    // back from play-testing
+    fn on_resume(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.build(data.world);
+    }
+
+    fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
+        let dimensions = (*data.world.read_resource::<ScreenDimensions>()).clone();
+        let (w, h) = (dimensions.width(), dimensions.height());
+        let mouse_pos = data.world.read_resource::<InputHandler<StringBindings>>().mouse_position();
+
+        match event {
+            StateEvent::Input(InputEvent::MouseButtonPressed(MouseButton::Left)) => {
+                let (x, y) = match mouse_pos {
+                    Some(pos) => pos,
+                    None => { return Trans::None; }
+                };
+                if x > w - PANEL_WIDTH * utils::dpi() {
+                    // converts to game coords
+                    match self.click_panel(data.world, x - w * 0.5, h * 0.5 - y) {
+                        Some(Trans::None) => {}
+                        Some(trans) => { return trans; }
+                        None => { return Trans::None; }
+                    }
+                } else {
+                    let pos = self.map_pos(data.world, (x, y));
+                    self.click_map(pos);
+                }
+                self.build(data.world);
+            }
+            StateEvent::Input(InputEvent::MouseMoved { .. }) => {
+                let pos = match mouse_pos {
+                    Some(pos) => self.map_pos(data.world, pos),
+                    None => { return Trans::None; }
+                };
+                if let Some(drag) = &mut self.drag {
+                    let delta = (snap(pos.0 - drag.start.0), snap(pos.1 - drag.start.1));
+                    let ((x, y), (width, height)) = drag.orig;
+                    drag.rect = if drag.resize {
+                        // the bottom edge follows the mouse down, so the height grows when y decreases
+                        ((x, y), ((width as i32 + delta.0).max(GRID) as u32, (height as i32 - delta.1).max(GRID) as u32))
+                    } else {
+                        ((x + delta.0, y + delta.1), (width, height))
+                    };
+                    self.update_drag(data.world);
+                }
+            }
+            StateEvent::Input(InputEvent::MouseButtonReleased(MouseButton::Left)) => {
+                if let (Some(drag), Some(selected)) = (self.drag.take(), &self.selected) {
+                    if drag.rect != drag.orig {
+                        selected.set_rect(&mut self.map, drag.rect.0, drag.rect.1);
+                        self.build(data.world);
+                    }
+                }
+            }
+            StateEvent::Window(Event::WindowEvent { event: WindowEvent::ReceivedCharacter(c), .. })
+                if self.editing.is_some() => {
+                self.type_char(c);
+                self.build(data.world);
+            }
+            StateEvent::Window(wevent) => {
+                if is_key_down(&wevent, VirtualKeyCode::Escape) {
+                    // cancel typing first, then the selection
+                    if self.editing.take().is_none() {
+                        self.selected = None;
+                        self.kind = None;
+                    }
+                    self.build(data.world);
+                    return Trans::None;
+                }
+                if self.editing.is_some() {
+                    return Trans::None;
+                }
+                if is_key_down(&wevent, VirtualKeyCode::Delete) {
+                    match self.selected.take() {
+                        Some(Selection::Object(name)) => { self.map.remove(&name); }
+                        Some(Selection::Description(name)) => { self.map.descriptions.retain(|obj| obj.name != name); }
+                        // the player cannot be deleted
+                        selected => { self.selected = selected; }
+                    }
+                    self.build(data.world);
+                }
+                if is_key_down(&wevent, VirtualKeyCode::P) {
+                    return self.play_test(data.world);
+                }
+                let pan = [
+                    (VirtualKeyCode::Left, (1., 0.)), (VirtualKeyCode::Right, (-1., 0.)),
+                    (VirtualKeyCode::Up, (0., -1.)), (VirtualKeyCode::Down, (0., 1.)),
+                ];
+                for (key, (dx, dy)) in pan.iter() {
+                    if is_key_down(&wevent, *key) {
+                        self.offset.0 += dx * PAN_STEP * utils::dpi();
+                        self.offset.1 += dy * PAN_STEP * utils::dpi();
+                        self.build(data.world);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Trans::None
+    }
+}
+
+// why the map cannot be written back to the level file without losing parts of it
+// instances and spawn commands are expanded when loading, and legacy levels would be overwritten with RON
+fn save_error(level: &levels::Level, map: &map::Map) -> Option<String> {
+    if level.path.ends_with(".json") {
+        return Some("legacy JSON levels cannot be saved from the editor".to_string());
+    }
+    if !map.prefabs.is_empty() {
+        return Some("levels with prefabs cannot be saved from the editor".to_string());
+    }
+    None
+}
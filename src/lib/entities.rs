@@ -1,15 +1,22 @@
 use amethyst::{
+    assets::{ AssetStorage, Loader },
     core::{ Transform, Parent },
     ecs::Entity,
     prelude::*,
-    renderer::Camera,
+    renderer::{
+        Camera, Texture,
+        rendy::{ hal::{ format::Format, image::{ Filter, Kind, SamplerInfo, ViewKind, WrapMode } }, texture::TextureBuilder },
+        types::TextureData,
+    },
     ui::{ Anchor, LineMode, UiImage, UiText, UiTransform },
     window::ScreenDimensions,
 };
 
-use crate::lib::components;
-use crate::lib::fonts;
-use crate::lib::utils;
+use crate::components;
+use crate::fonts;
+use crate::render;
+use crate::save;
+use crate::utils;
 
 // Camera
 pub fn init_camera(world: &mut World) {
@@ -97,6 +104,40 @@ impl Label {
     }
 }
 
+// Picture: an image drawn at runtime, e.g. a level thumbnail
+pub struct Picture {
+    pub canvas: render::Canvas,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Picture {
+    pub fn new(canvas: render::Canvas, width: f32, height: f32) -> Self {
+        Picture { canvas, width, height }
+    }
+
+    pub fn instantiate(&self, id: String, world: &mut World, x: f32, y: f32, z: f32) -> Entity {
+        let (w, h) = (self.canvas.width, self.canvas.height);
+        let builder = TextureBuilder::new()
+            .with_kind(Kind::D2(w, h, 1, 1))
+            .with_view_kind(ViewKind::D2)
+            .with_data_width(w)
+            .with_data_height(h)
+            .with_sampler_info(SamplerInfo::new(Filter::Linear, WrapMode::Clamp))
+            .with_raw_data(self.canvas.pixels.clone(), Format::Rgba8Srgb);
+        let texture = world.read_resource::<Loader>()
+            .load_from_data(TextureData(builder), (), &world.read_resource::<AssetStorage<Texture>>());
+
+        world.create_entity()
+            .with(UiTransform::new(
+                id, Anchor::Middle, Anchor::Middle,
+                x * utils::dpi(), y * utils::dpi(), z,
+                self.width * utils::dpi(), self.height * utils::dpi()))
+            .with(UiImage::Texture(texture))
+            .build()
+    }
+}
+
 // Scoreboard
 #[derive(Default)]
 pub struct Scoreboard {
@@ -123,13 +164,138 @@ impl Scoreboard {
     }
 
     pub fn add_score(&mut self, world: &mut World, num: i32) {
-        self.score += num;
+        self.set_score(world, self.score + num);
+    }
+
+    pub fn set_score(&mut self, world: &mut World, score: i32) {
+        self.score = score;
         if self.entity.is_none() { return; }
         world.write_storage::<UiText>().get_mut(self.entity.unwrap()).unwrap()
             .text = "Score: ".to_string() + &self.score.to_string();
     }
 }
 
+// HudText: a line of text on the HUD (lives, keys, ...)
+#[derive(Default)]
+pub struct HudText {
+    pub entity: Option<Entity>,
+}
+
+impl HudText {
+    pub fn instantiate(&mut self, id: String, world: &mut World, text: String, x: f32, y: f32, z: f32) -> Entity {
+        let font = fonts::Fonts::instance().get("digital-7-mono.ttf".to_string(), world);
+
+        self.entity = Some(world.create_entity()
+            .with(UiTransform::new(
+                id, Anchor::TopLeft, Anchor::MiddleLeft,
+                x * utils::dpi(), y * utils::dpi(), z,
+                400. * utils::dpi(), 80. * utils::dpi()))
+            .with(UiText::new(
+                font.clone(), text,
+                utils::get_color(utils::BLACK), 20. * utils::dpi(),
+                LineMode::Single, Anchor::MiddleLeft))
+            .build());
+
+        self.entity.unwrap()
+    }
+
+    pub fn set_text(&self, world: &mut World, text: String) {
+        if self.entity.is_none() { return; }
+        world.write_storage::<UiText>().get_mut(self.entity.unwrap()).unwrap().text = text;
+    }
+}
+
+// Timer: counts fixed updates and compares the splits with the personal best
+#[derive(Default)]
+pub struct Timer {
+    pub ticks: u32,
+    pub splits: Vec<save::Split>,
+    pub pb_splits: Vec<save::Split>,  // splits of the personal best run
+    pub entity: Option<Entity>,
+    pub delta_entity: Option<Entity>,
+}
+
+impl Timer {
+    pub fn new(pb_splits: Vec<save::Split>) -> Self {
+        Timer { pb_splits, ..Default::default() }
+    }
+
+    pub fn instantiate(&mut self, id: String, world: &mut World, x: f32, y: f32, z: f32) -> Entity {
+        let font = fonts::Fonts::instance().get("digital-7-mono.ttf".to_string(), world).clone();
+
+        self.entity = Some(world.create_entity()
+            .with(UiTransform::new(
+                id.clone(), Anchor::TopLeft, Anchor::MiddleLeft,
+                x * utils::dpi(), y * utils::dpi(), z,
+                200. * utils::dpi(), 80. * utils::dpi()))
+            .with(UiText::new(
+                font.clone(), "Time: ".to_string() + &utils::format_ticks(0),
+                utils::get_color(utils::BLACK), 20. * utils::dpi(),
+                LineMode::Single, Anchor::MiddleLeft))
+            .build());
+        self.delta_entity = Some(world.create_entity()
+            .with(UiTransform::new(
+                id + "-delta", Anchor::TopLeft, Anchor::MiddleLeft,
+                (x + 200.) * utils::dpi(), y * utils::dpi(), z,
+                200. * utils::dpi(), 80. * utils::dpi()))
+            .with(UiText::new(
+                font, "".to_string(),
+                utils::get_color(utils::BLACK), 20. * utils::dpi(),
+                LineMode::Single, Anchor::MiddleLeft))
+            .build());
+
+        self.entity.unwrap()
+    }
+
+    // advance by one fixed update
+    pub fn tick(&mut self, world: &mut World) {
+        self.ticks += 1;
+        if self.entity.is_none() { return; }
+        self.show_time(world);
+
+        // already behind the personal best before reaching the next checkpoint of the personal best
+        let next = self.pb_splits.iter().find(|pb| !self.splits.iter().any(|split| split.name == pb.name));
+        if let Some(pb) = next {
+            if self.ticks > pb.ticks {
+                self.show_delta(world, self.ticks as i64 - pb.ticks as i64);
+            }
+        }
+    }
+
+    // go back to an earlier time, dropping the splits made after it
+    pub fn rewind_to(&mut self, world: &mut World, ticks: u32) {
+        self.ticks = ticks;
+        self.splits.retain(|split| split.ticks <= ticks);
+        if self.entity.is_none() { return; }
+        self.show_time(world);
+    }
+
+    fn show_time(&self, world: &mut World) {
+        world.write_storage::<UiText>().get_mut(self.entity.unwrap()).unwrap()
+            .text = "Time: ".to_string() + &utils::format_ticks(self.ticks);
+    }
+
+    // splits are keyed by checkpoint name, a checkpoint reached again after respawning is not split again
+    pub fn split(&mut self, world: &mut World, name: String) {
+        if self.splits.iter().any(|split| split.name == name) { return; }
+        let pb_ticks = self.pb_splits.iter().find(|pb| pb.name == name).map(|pb| pb.ticks);
+        self.splits.push(save::Split { name, ticks: self.ticks });
+        if let Some(pb_ticks) = pb_ticks {
+            self.show_delta(world, self.ticks as i64 - pb_ticks as i64);
+        }
+    }
+
+    // green if ahead of the personal best, red if behind
+    fn show_delta(&self, world: &mut World, delta: i64) {
+        if self.delta_entity.is_none() { return; }
+        let mut text_store = world.write_storage::<UiText>();
+        let text = text_store.get_mut(self.delta_entity.unwrap()).unwrap();
+        let sign = if delta > 0 { "+" } else { "-" };
+        text.text = sign.to_string() + &utils::format_ticks(delta.unsigned_abs() as u32);
+        text.color = utils::get_color(if delta > 0 { utils::RED } else { utils::GREEN });
+    }
+}
+
 // Button
 pub struct Button {
     pos: [f32; 3],
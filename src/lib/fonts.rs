@@ -1,42 +1,215 @@
 #![allow(dead_code)]
 
 use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use amethyst::{
     prelude::*,
-    assets::Loader,
+    assets::{ Directory, Loader },
     ui::{ FontHandle, TtfFormat },
+    utils::application_root_dir,
 };
 
+use crate::levels;
+
 static mut INSTANCE: Option<Fonts> = None;
 
+// Font manifest: maps logical family names (e.g. "uasquared") to files under assets/fonts
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    pub default: String,
+    pub fonts: HashMap<String, String>,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        let mut fonts = HashMap::new();
+        fonts.insert("merriweather".to_string(), "merriweather.ttf".to_string());
+        Manifest { default: "merriweather".to_string(), fonts }
+    }
+}
+
+impl Manifest {
+    pub fn from_file(file_name: String) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(file_name)?;
+        let obj = ron::from_str(contents.as_str())?;
+        Ok(obj)
+    }
+
+    // find the file of a font family
+    // accepts both the logical name and the file name, with or without the extension
+    pub fn resolve(&self, font_family: &str) -> Option<String> {
+        let name = font_family.strip_suffix(".ttf").unwrap_or(font_family);
+        if let Some(file) = self.fonts.get(name) {
+            return Some(file.clone());
+        }
+        for file in self.fonts.values() {
+            if file == font_family || file.strip_suffix(".ttf") == Some(name) {
+                return Some(file.clone());
+            }
+        }
+        None
+    }
+
+    // the default font is used for unknown families, so it has to be in the manifest itself
+    pub fn check(&self) -> Result<(), String> {
+        match self.resolve(&self.default) {
+            Some(_) => Ok(()),
+            None => Err(format!("default font \"{}\" is not in the manifest", self.default)),
+        }
+    }
+}
+
 pub struct Fonts {
+    manifest: Manifest,
+    pack: Option<(String, Manifest)>,  // asset source and fonts of the active level pack
     fonts: HashMap<String, FontHandle>
 }
 
 impl Fonts {
     fn new() -> Self {
-        Fonts{ fonts: HashMap::default() }
+        let manifest_path = application_root_dir().unwrap()
+            .join("assets").join("fonts").join("manifest.ron")
+            .into_os_string().into_string().unwrap();
+        let manifest = match Manifest::from_file(manifest_path.clone()) {
+            Ok(manifest) => match manifest.check() {
+                Ok(()) => manifest,
+                Err(err) => {
+                    log::error!("Invalid font manifest \"{}\": {}", manifest_path, err);
+                    Manifest::default()
+                }
+            },
+            Err(err) => {
+                log::error!("Failed to load font manifest \"{}\": {}", manifest_path, err);
+                Manifest::default()
+            }
+        };
+        Fonts{ manifest, pack: None, fonts: HashMap::default() }
     }
 
     pub fn instance() -> &'static mut Self {
         unsafe {
             if INSTANCE.is_none() {
-                INSTANCE = Some(Fonts{ fonts: HashMap::default() });
+                INSTANCE = Some(Fonts::new());
             }
             INSTANCE.as_mut().unwrap()
         }
     }
 
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn resolve(&self, font_family: &str) -> Option<String> {
+        self.locate(font_family).map(|(_, file)| file)
+    }
+
+    // find the asset source and the file of a font family
+    // fonts of the active level pack take precedence over the built-in fonts
+    fn locate(&self, font_family: &str) -> Option<(String, String)> {
+        if let Some((source, manifest)) = &self.pack {
+            if let Some(file) = manifest.resolve(font_family) {
+                return Some((source.clone(), file));
+            }
+        }
+        self.manifest.resolve(font_family).map(|file| (String::new(), file))
+    }
+
+    // make the fonts of a level pack available, replacing those of the previous pack
+    pub fn use_pack(&mut self, world: &World, pack: &levels::Pack) {
+        let source = "pack-".to_string() + &pack.id;
+        world.write_resource::<Loader>().add_source(source.clone(), Directory::new(pack.dir.clone()));
+        let manifest = Manifest { default: self.manifest.default.clone(), fonts: pack.fonts.clone() };
+        self.pack = Some((source, manifest));
+        self.fonts.clear();
+    }
+
     pub fn get(&mut self, font_family: String, world: &World) -> &FontHandle {
         // add if not already loaded
         if !self.fonts.contains_key(&font_family) {
+            // unknown families fall back to the default font
+            let (source, file) = match self.locate(&font_family) {
+                Some(location) => location,
+                None => {
+                    log::error!("Unknown font family \"{}\", falling back to \"{}\"",
+                        font_family, self.manifest.default);
+                    // checked when the manifest was loaded
+                    self.locate(&self.manifest.default.clone()).expect("default font is not in the manifest")
+                }
+            };
             let mut font_address = "fonts/".to_string();
-            font_address.push_str(&font_family.clone());
-            let font = world.read_resource::<Loader>().load(
-                font_address, TtfFormat, (), &world.read_resource(),
+            font_address.push_str(&file);
+            let font = world.read_resource::<Loader>().load_from(
+                font_address, TtfFormat, source.as_str(), (), &world.read_resource(),
             );
             self.fonts.insert(font_family.to_string(), font);
         }
         self.fonts.get(&font_family).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(fonts: &[(&str, &str)]) -> Manifest {
+        Manifest {
+            default: "merriweather".to_string(),
+            fonts: fonts.iter().map(|(name, file)| (name.to_string(), file.to_string())).collect(),
+        }
+    }
+
+    fn fonts_with_pack(pack_fonts: &[(&str, &str)]) -> Fonts {
+        Fonts {
+            manifest: manifest(&[("merriweather", "merriweather.ttf"), ("uasquared", "uasquared.ttf")]),
+            pack: Some(("pack-test".to_string(), manifest(pack_fonts))),
+            fonts: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn resolves_names_and_files() {
+        let manifest = manifest(&[("uasquared", "uasquared.ttf")]);
+        assert_eq!(manifest.resolve("uasquared"), Some("uasquared.ttf".to_string()));
+        assert_eq!(manifest.resolve("uasquared.ttf"), Some("uasquared.ttf".to_string()));
+    }
+
+    #[test]
+    fn unknown_fonts_are_not_resolved() {
+        assert_eq!(manifest(&[("uasquared", "uasquared.ttf")]).resolve("missing"), None);
+        assert_eq!(manifest(&[]).resolve("merriweather"), None);
+    }
+
+    #[test]
+    fn the_default_manifest_resolves_its_default() {
+        let manifest = Manifest::default();
+        assert_eq!(manifest.resolve(&manifest.default), Some("merriweather.ttf".to_string()));
+        assert_eq!(manifest.check(), Ok(()));
+    }
+
+    #[test]
+    fn a_default_missing_from_the_manifest_is_an_error() {
+        let manifest = manifest(&[("uasquared", "uasquared.ttf")]);
+        assert_eq!(manifest.check(), Err("default font \"merriweather\" is not in the manifest".to_string()));
+    }
+
+    #[test]
+    fn the_game_manifest_resolves_its_default() {
+        let manifest = Manifest::from_file("assets/fonts/manifest.ron".to_string()).unwrap();
+        assert_eq!(manifest.check(), Ok(()));
+    }
+
+    #[test]
+    fn pack_fonts_take_precedence() {
+        let fonts = fonts_with_pack(&[("uasquared", "pack-uasquared.ttf"), ("pixel", "pixel.ttf")]);
+        assert_eq!(fonts.locate("pixel"), Some(("pack-test".to_string(), "pixel.ttf".to_string())));
+        assert_eq!(fonts.locate("uasquared"), Some(("pack-test".to_string(), "pack-uasquared.ttf".to_string())));
+    }
+
+    #[test]
+    fn falls_back_to_the_built_in_fonts() {
+        let fonts = fonts_with_pack(&[("pixel", "pixel.ttf")]);
+        assert_eq!(fonts.locate("merriweather"), Some((String::new(), "merriweather.ttf".to_string())));
+        assert_eq!(fonts.locate(&fonts.manifest().default), Some((String::new(), "merriweather.ttf".to_string())));
+        assert_eq!(fonts.locate("missing"), None);
+    }
+}
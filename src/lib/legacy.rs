@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::map;
+use crate::utils;
+
+// Legacy JSON levels (4.json - 10.json)
+// they use a y-down coordinate system, so every y coordinate and y speed is flipped on conversion
+#[derive(Debug, Deserialize)]
+pub struct Level {
+    pub player: Player,
+    pub map: Map,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Player {
+    pub pos: Vec<(i32, i32)>,
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Map {
+    pub size: (u32, u32),
+    #[serde(default)]
+    pub target: Vec<Rect>,
+    #[serde(default)]
+    pub coin: Vec<Rect>,
+    #[serde(default)]
+    pub switch: Vec<Switch>,
+    #[serde(default)]
+    pub monster: Vec<Movable>,
+    #[serde(default)]
+    pub elevator: Vec<Movable>,
+    #[serde(default)]
+    pub obstacle: Vec<Rect>,
+    #[serde(default)]
+    pub description: Vec<Description>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Rect {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Switch {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    pub command: HashMap<String, Vec<Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Movable {
+    pub name: String,
+    pub track: Vec<Track>,
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Track {
+    pub pos: (i32, i32),
+    pub speed: (i32, i32),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Description {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub text: Vec<String>,
+    pub font: (String, u32),
+    pub color: (u32, u32, u32),
+}
+
+impl Level {
+    pub fn into_map(self) -> map::Map {
+        let height = self.map.size.1 as i32;
+        let flip = |pos: (i32, i32)| (pos.0, height - pos.1);
+        let rect = |r: &Rect| (r.name.clone(), flip(r.pos), r.size, r.color);
+        let track = |tracks: &Vec<Track>| tracks.iter()
+            .map(|tr| map::Track { pos: flip(tr.pos), speed: (tr.speed.0, -tr.speed.1) })
+            .collect::<Vec<map::Track>>();
+
+        map::Map {
+            size: self.map.size,
+            player: map::Player {
+                pos: flip(self.player.pos.first().cloned().unwrap_or((0, 0))),
+                size: self.player.size,
+                color: self.player.color,
+                health: utils::PLAYER_HEALTH,
+            },
+            targets: self.map.target.iter().map(rect)
+                .map(|(name, pos, size, color)| map::Target { name, pos, size, color }).collect(),
+            coins: self.map.coin.iter().map(rect)
+                .map(|(name, pos, size, color)| map::Coin { name, pos, size, color }).collect(),
+            switches: self.map.switch.iter().map(|s| map::Switch {
+                name: s.name.clone(), pos: flip(s.pos), size: s.size, color: s.color,
+                command: s.command.iter()
+                    .map(|(state, commands)| (
+                        state.clone(),
+                        commands.iter().map(|c| convert_command(c, height)).collect()))
+                    .collect(),
+            }).collect(),
+            monsters: self.map.monster.iter().map(|m| map::Monster {
+                name: m.name.clone(), track: track(&m.track), path: None, size: m.size, color: m.color,
+                damage: None, stompable: false, behavior: None,
+            }).collect(),
+            elevators: self.map.elevator.iter().map(|e| map::Elevator {
+                name: e.name.clone(), track: track(&e.track), path: None, size: e.size, color: e.color,
+                one_way: false,
+            }).collect(),
+            obstacles: self.map.obstacle.iter().map(rect)
+                .map(|(name, pos, size, color)| map::Obstacle { name, pos, size, color, one_way: false }).collect(),
+            descriptions: self.map.description.iter().map(|d| {
+                // legacy descriptions have no size, so make the box fit the text
+                let longest = d.text.iter().map(|line| line.len()).max().unwrap_or(0) as i32;
+                map::Description {
+                    name: d.name.clone(),
+                    pos: flip(d.pos),
+                    size: (longest * d.font.1 as i32, d.text.len() as i32 * d.font.1 as i32 * 2),
+                    text: d.text.join("\n"),
+                    font: map::Font { family: d.font.0.clone(), size: d.font.1 },
+                    color: d.color,
+                }
+            }).collect(),
+            checkpoints: vec![],
+            lives: 1,
+            rewind: None,
+            keys: vec![],
+            doors: vec![],
+            springs: vec![],
+            hazards: vec![],
+            turrets: vec![],
+            prefabs: HashMap::new(),
+            instances: vec![],
+        }
+    }
+}
+
+// convert a legacy command into the string list used by InteractableComp
+// nested commands ("if") are kept as JSON text, map::from_file checks the result like any other command
+fn convert_command(command: &Value, height: i32) -> Vec<String> {
+    let args = match command.as_array() {
+        Some(args) => args,
+        None => { return vec![]; }
+    };
+    let name = args.get(0).and_then(|v| v.as_str()).unwrap_or("");
+    match name {
+        "color" => {
+            let mut result = vec![name.to_string()];
+            for arg in args.iter().skip(1) {
+                match arg.as_array() {
+                    Some(rgb) => { result.extend(rgb.iter().map(to_string)); }
+                    None => { result.push(to_string(arg)); }
+                }
+            }
+            result
+        }
+        "object" => {
+            let mut result: Vec<String> = args.iter().map(to_string).collect();
+            if result.len() == 4 {
+                if let Ok((x, y)) = serde_json::from_str::<(i32, i32)>(&result[3]) {
+                    match result[2].as_str() {
+                        "pos" => { result[3] = format!("[{}, {}]", x, height - y); }
+                        "speed" => { result[3] = format!("[{}, {}]", x, -y); }
+                        _ => {}
+                    }
+                }
+            }
+            result
+        }
+        "if" => {
+            let condition = args.get(1).map(|c| convert_command(c, height)).unwrap_or(vec![]);
+            let body: Vec<Vec<String>> = args.get(2).and_then(|b| b.as_array())
+                .map(|b| b.iter().map(|c| convert_command(c, height)).collect())
+                .unwrap_or(vec![]);
+            vec![
+                name.to_string(),
+                serde_json::to_string(&condition).unwrap(),
+                serde_json::to_string(&body).unwrap(),
+            ]
+        }
+        _ => args.iter().map(to_string).collect(),
+    }
+}
+
+fn to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use amethyst::utils::application_root_dir;
+
+use crate::map;
+use crate::utils;
+
+// Level manifest: lists the levels of a level pack
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub name: Option<String>,  // display name of the pack, defaults to the directory name
+    #[serde(default)]
+    pub fonts: HashMap<String, String>,  // fonts shipped with the pack, relative to <pack>/fonts
+    #[serde(default)]
+    pub strict_tracks: bool,  // reject levels with tracks that do not loop instead of warning
+    pub levels: Vec<LevelInfo>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LevelInfo {
+    pub id: String,
+    pub title: String,
+    pub file: String,  // relative to the directory of the manifest
+    #[serde(default)]
+    pub order: i32,  // levels are sorted by order, ties keep the manifest order
+}
+
+impl Manifest {
+    pub fn from_file(file_name: String) -> Result<Manifest, Box<dyn std::error::Error>> {
+        let contents = std::fs::read_to_string(file_name)?;
+        let obj = ron::from_str(contents.as_str())?;
+        Ok(obj)
+    }
+
+    pub fn to_file(&self, file_name: String) -> Result<(), Box<dyn std::error::Error>> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(file_name, contents)?;
+        Ok(())
+    }
+}
+
+// A level pack: a directory with a manifest, its levels and its fonts
+#[derive(Debug, Clone, Default)]
+pub struct Pack {
+    pub id: String,
+    pub name: String,
+    pub dir: PathBuf,
+    pub levels: Vec<LevelInfo>,  // sorted by order
+    pub fonts: HashMap<String, String>,
+    pub strict_tracks: bool,
+    pub valid: bool,  // false if the manifest fails to parse
+}
+
+impl Pack {
+    fn load(id: String, dir: PathBuf) -> Pack {
+        let manifest_path = dir.join("manifest.ron").into_os_string().into_string().unwrap();
+        match Manifest::from_file(manifest_path.clone()) {
+            Ok(mut manifest) => {
+                manifest.levels.sort_by_key(|info| info.order);
+                Pack {
+                    name: manifest.name.unwrap_or(id.clone()),
+                    id, dir, levels: manifest.levels, fonts: manifest.fonts, strict_tracks: manifest.strict_tracks,
+                    valid: true,
+                }
+            }
+            Err(err) => {
+                log::error!("Failed to load level manifest \"{}\": {}", manifest_path, err);
+                Pack { name: id.clone(), id, dir, ..Default::default() }
+            }
+        }
+    }
+
+    pub fn first_level_id(&self) -> Option<&String> {
+        self.levels.first().map(|info| &info.id)
+    }
+
+    pub fn next_level_id(&self, id: &String) -> Option<&String> {
+        let index = self.levels.iter().position(|info| &info.id == id)?;
+        self.levels.get(index + 1).map(|info| &info.id)
+    }
+
+    // the full path of a level file
+    pub fn level_path(&self, info: &LevelInfo) -> String {
+        self.dir.join(&info.file).into_os_string().into_string().unwrap()
+    }
+
+    // check every level file
+    pub fn load_levels(&self) -> Vec<Level> {
+        self.levels.iter().map(|info| {
+            let path = self.level_path(info);
+            let loaded = if self.strict_tracks { map::from_file_strict(path.clone()) } else { map::from_file(path.clone()) };
+            let valid = match loaded {
+                Ok(_) => true,
+                Err(err) => {
+                    log::error!("Failed to load level \"{}\" ({}): {}", info.id, path, err);
+                    false
+                }
+            };
+            Level { pack: self.clone(), info: info.clone(), path, valid }
+        }).collect()
+    }
+}
+
+// A level entry ready to be shown and played
+#[derive(Debug, Clone, Default)]
+pub struct Level {
+    pub pack: Pack,  // the pack the level belongs to
+    pub info: LevelInfo,
+    pub path: String,  // full path of the level file
+    pub valid: bool,  // false if the level file fails to parse
+}
+
+impl Level {
+    pub fn load_map(&self) -> Result<map::Map, Box<dyn std::error::Error>> {
+        if self.pack.strict_tracks { map::from_file_strict(self.path.clone()) } else { map::from_file(self.path.clone()) }
+    }
+}
+
+// directory containing the built-in levels
+pub fn levels_dir() -> PathBuf {
+    application_root_dir().unwrap().join("assets").join("levels")
+}
+
+// directory containing the levels installed by the user
+pub fn user_levels_dir() -> PathBuf {
+    utils::data_dir().join("levels")
+}
+
+// the level of the editor, saved as a user level pack so that it can also be played from the menu
+pub fn editor_level() -> Level {
+    let dir = user_levels_dir().join("editor");
+    let info = LevelInfo {
+        id: "level".to_string(), title: "Edited level".to_string(), file: "level.ron".to_string(), order: 0,
+    };
+    let pack = Pack {
+        id: "user-editor".to_string(), name: "Editor".to_string(), dir: dir.clone(), levels: vec![info.clone()],
+        fonts: HashMap::new(), strict_tracks: false, valid: true,
+    };
+    Level { pack, path: dir.join(&info.file).into_os_string().into_string().unwrap(), info, valid: true }
+}
+
+// find all level packs
+// the built-in levels come first, followed by the packs under assets/levels and the user data dir
+pub fn load_packs() -> Vec<Pack> {
+    let mut packs = vec![Pack::load("main".to_string(), levels_dir())];
+    for (prefix, root) in [("", levels_dir()), ("user-", user_levels_dir())].iter() {
+        let entries = match std::fs::read_dir(root) {
+            Ok(entries) => entries,
+            Err(_) => { continue; }
+        };
+        let mut dirs: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir() && path.join("manifest.ron").is_file())
+            .collect();
+        dirs.sort();
+        for dir in dirs {
+            let id = prefix.to_string() + &dir.file_name().unwrap().to_string_lossy();
+            // the id keys the saved progress, so a directory named like another pack (e.g. "main") is skipped
+            if packs.iter().any(|pack| pack.id == id) {
+                log::warn!("Level pack \"{}\" is skipped, its id \"{}\" is already used", dir.display(), id);
+                continue;
+            }
+            packs.push(Pack::load(id, dir));
+        }
+    }
+    packs
+}
+
+// level files given on the command line, directories are searched for .ron and .json files
+// without arguments, every level listed by the manifest of every level pack
+pub fn level_files(args: Vec<String>) -> Vec<String> {
+    if args.is_empty() {
+        return load_packs().iter()
+            .flat_map(|pack| pack.levels.iter().map(move |info| pack.level_path(info)))
+            .collect();
+    }
+
+    let mut files = vec![];
+    for arg in args {
+        let path = PathBuf::from(&arg);
+        if !path.is_dir() {
+            files.push(arg);
+            continue;
+        }
+        let mut entries: Vec<PathBuf> = match std::fs::read_dir(&path) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+            Err(err) => {
+                eprintln!("Failed to read directory \"{}\": {}", arg, err);
+                continue;
+            }
+        };
+        entries.sort();
+        for entry in entries {
+            let is_level = matches!(entry.extension().and_then(|ext| ext.to_str()), Some("ron") | Some("json"))
+                && entry.file_name().map_or(false, |name| name != "manifest.ron");
+            if is_level {
+                files.push(entry.into_os_string().into_string().unwrap());
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a pack directory with the given manifest
+    fn load_pack(name: &str, manifest: &str) -> Pack {
+        let dir = std::env::temp_dir().join(format!("platformer-pack-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("manifest.ron"), manifest).unwrap();
+        let pack = Pack::load(name.to_string(), dir.clone());
+        std::fs::remove_dir_all(&dir).unwrap_or(());
+        pack
+    }
+
+    #[test]
+    fn levels_unlock_in_manifest_order() {
+        let pack = load_pack("order", r#"(
+            name: Some("Order"),
+            levels: [
+                (id: "c", title: "C", file: "c.ron", order: 2),
+                (id: "a", title: "A", file: "a.ron"),
+                (id: "b", title: "B", file: "b.ron", order: 2),
+            ],
+        )"#);
+        assert!(pack.valid);
+        assert_eq!(pack.name, "Order");
+        assert_eq!(pack.first_level_id(), Some(&"a".to_string()));
+        assert_eq!(pack.next_level_id(&"a".to_string()), Some(&"c".to_string()));
+        assert_eq!(pack.next_level_id(&"c".to_string()), Some(&"b".to_string()));
+        assert_eq!(pack.next_level_id(&"b".to_string()), None);
+        assert_eq!(pack.next_level_id(&"missing".to_string()), None);
+    }
+
+    #[test]
+    fn broken_manifests_give_an_invalid_pack() {
+        let pack = load_pack("broken", "(levels: [");
+        assert!(!pack.valid);
+        assert_eq!(pack.name, "broken");
+        assert!(pack.levels.is_empty());
+    }
+}
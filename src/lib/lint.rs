@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde::Serialize;
+use amethyst::utils::application_root_dir;
+
+use crate::components;
+use crate::fonts;
+use crate::levels;
+use crate::map;
+
+// Level checks that do not need a window, used by platformer-lint
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,  // the level fails to load, panics or has objects drifting off their track
+    Warning,  // the level loads but probably does not play as intended
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub code: String,  // stable identifier of the check, e.g. "duplicate-name"
+    pub object: Option<String>,
+    pub message: String,
+}
+
+// The result of checking one level file
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub file: String,
+    pub ok: bool,  // false if there is any error
+    pub issues: Vec<Issue>,
+}
+
+impl Issue {
+    fn new(severity: Severity, code: &str, object: Option<&String>, message: String) -> Self {
+        Issue { severity, code: code.to_string(), object: object.cloned(), message }
+    }
+}
+
+// load a level and check it
+pub fn lint_file(file_name: String) -> Report {
+    let issues = match map::from_file(file_name.clone()) {
+        Ok(map) => lint_map(&map, &font_manifests(&file_name)),
+        Err(err) => vec![Issue::new(Severity::Error, "parse", None, err.to_string())],
+    };
+    Report { ok: issues.iter().all(|issue| issue.severity != Severity::Error), file: file_name, issues }
+}
+
+// the game fonts, and the fonts of the level pack if the level is in one
+fn font_manifests(file_name: &String) -> Vec<fonts::Manifest> {
+    let mut manifests = vec![];
+    let game_fonts = application_root_dir().unwrap().join("assets").join("fonts").join("manifest.ron");
+    match fonts::Manifest::from_file(game_fonts.into_os_string().into_string().unwrap()) {
+        Ok(manifest) => manifests.push(manifest),
+        Err(_) => manifests.push(fonts::Manifest::default()),
+    }
+    let pack_manifest = Path::new(file_name).with_file_name("manifest.ron");
+    if let Ok(manifest) = levels::Manifest::from_file(pack_manifest.into_os_string().into_string().unwrap()) {
+        manifests.push(fonts::Manifest { default: "".to_string(), fonts: manifest.fonts });
+    }
+    manifests
+}
+
+pub fn lint_map(map: &map::Map, fonts: &Vec<fonts::Manifest>) -> Vec<Issue> {
+    let mut issues = vec![];
+    check_names(map, &mut issues);
+    check_commands(map, &mut issues);
+    check_tracks(map, &mut issues);
+    check_bounds(map, &mut issues);
+    check_fonts(map, fonts, &mut issues);
+    issues
+}
+
+// every object and its rectangle(s), movables have one per waypoint
+fn rects(map: &map::Map) -> Vec<(String, (i32, i32), (u32, u32))> {
+    let mut result = vec![("player".to_string(), map.player.pos, map.player.size)];
+    result.extend(map.targets.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.coins.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.switches.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    for obj in map.monsters.iter() {
+        result.extend(obj.track.iter().map(|tr| (obj.name.clone(), tr.pos, obj.size)));
+        result.extend(obj.path.iter().flat_map(|path| path.waypoints.iter())
+            .map(|wp| (obj.name.clone(), wp.pos, obj.size)));
+    }
+    for obj in map.elevators.iter() {
+        result.extend(obj.track.iter().map(|tr| (obj.name.clone(), tr.pos, obj.size)));
+        result.extend(obj.path.iter().flat_map(|path| path.waypoints.iter())
+            .map(|wp| (obj.name.clone(), wp.pos, obj.size)));
+    }
+    result.extend(map.obstacles.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.checkpoints.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.keys.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.doors.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.springs.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.hazards.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result.extend(map.turrets.iter().map(|obj| (obj.name.clone(), obj.pos, obj.size)));
+    result
+}
+
+fn check_names(map: &map::Map, issues: &mut Vec<Issue>) {
+    let mut names = map.object_names();
+    names.push("player".to_string());
+    names.extend(map.descriptions.iter().map(|obj| obj.name.clone()));
+
+    let mut counts: HashMap<&String, usize> = HashMap::new();
+    for name in names.iter() {
+        *counts.entry(name).or_insert(0) += 1;
+    }
+    let mut duplicates: Vec<(&&String, &usize)> = counts.iter().filter(|(_, count)| **count > 1).collect();
+    duplicates.sort();
+    for (name, count) in duplicates {
+        issues.push(Issue::new(Severity::Error, "duplicate-name", Some(name),
+            format!("\"{}\" is used by {} objects", name, count)));
+    }
+}
+
+// the names commands can refer to: the objects of the level and those created by spawn commands
+// commands are checked when the level loads, so they all parse here
+fn known_names(map: &map::Map) -> Vec<String> {
+    let mut names = map.object_names();
+    names.push("player".to_string());
+    for switch in map.switches.iter() {
+        for command in switch.command.values().flatten().filter_map(|command| components::Command::parse(command).ok()) {
+            names.extend(command.spawned());
+        }
+    }
+    names
+}
+
+fn check_commands(map: &map::Map, issues: &mut Vec<Issue>) {
+    let names = known_names(map);
+    for switch in map.switches.iter() {
+        for command in switch.command.values().flatten() {
+            let targets = components::Command::parse(command).map_or(vec![], |parsed| parsed.targets());
+            for name in targets.iter().filter(|name| !names.contains(name)) {
+                issues.push(Issue::new(Severity::Error, "unknown-target", Some(&switch.name),
+                    format!("command {:?} refers to unknown object \"{}\"", command, name)));
+            }
+        }
+    }
+}
+
+fn check_tracks(map: &map::Map, issues: &mut Vec<Issue>) {
+    // an empty track makes the level panic when it starts
+    let is_empty = |track: &Vec<map::Track>, path: &Option<map::Path>| match path {
+        Some(path) => path.waypoints.is_empty(),
+        None => track.is_empty(),
+    };
+    let mut empty: Vec<&String> = vec![];
+    empty.extend(map.monsters.iter().filter(|obj| is_empty(&obj.track, &obj.path)).map(|obj| &obj.name));
+    empty.extend(map.elevators.iter().filter(|obj| is_empty(&obj.track, &obj.path)).map(|obj| &obj.name));
+    for name in empty.iter() {
+        issues.push(Issue::new(Severity::Error, "empty-track", Some(name), "the track has no waypoint".to_string()));
+    }
+
+    for (name, err) in map.check_tracks() {
+        if empty.contains(&&name) { continue; }
+        // the game only warns unless the pack has strict_tracks, but the object drifts away either way
+        issues.push(Issue::new(Severity::Error, "unreachable-waypoint", Some(&name), err.to_string()));
+    }
+}
+
+fn check_bounds(map: &map::Map, issues: &mut Vec<Issue>) {
+    let (width, height) = (map.size.0 as i32, map.size.1 as i32);
+    let mut reported: Vec<String> = vec![];
+    for (name, pos, size) in rects(map) {
+        // positions are the top left corner with y pointing up
+        let inside = pos.0 >= 0 && pos.0 + size.0 as i32 <= width && pos.1 - size.1 as i32 >= 0 && pos.1 <= height;
+        if !inside && !reported.contains(&name) {
+            issues.push(Issue::new(Severity::Warning, "out-of-bounds", Some(&name),
+                format!("{:?} with size {:?} is outside the map of size {:?}", pos, size, map.size)));
+            reported.push(name);
+        }
+    }
+}
+
+fn check_fonts(map: &map::Map, fonts: &Vec<fonts::Manifest>, issues: &mut Vec<Issue>) {
+    for description in map.descriptions.iter() {
+        if fonts.iter().all(|manifest| manifest.resolve(&description.font.family).is_none()) {
+            issues.push(Issue::new(Severity::Warning, "unknown-font", Some(&description.name),
+                format!("font family \"{}\" is not installed, the default font is used", description.font.family)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(map: &map::Map) -> Vec<(Severity, String, Option<String>)> {
+        lint_map(map, &vec![fonts::Manifest::default()]).into_iter()
+            .map(|issue| (issue.severity, issue.code, issue.object))
+            .collect()
+    }
+
+    fn issue(severity: Severity, code: &str, object: &str) -> (Severity, String, Option<String>) {
+        (severity, code.to_string(), Some(object.to_string()))
+    }
+
+    fn obstacle(name: &str, pos: (i32, i32)) -> map::Obstacle {
+        map::Obstacle { name: name.to_string(), pos, size: (10, 10), color: (0, 0, 0), one_way: false }
+    }
+
+    fn elevator(name: &str, track: Vec<map::Track>) -> map::Elevator {
+        map::Elevator { name: name.to_string(), track, path: None, size: (10, 10), color: (0, 0, 0), one_way: false }
+    }
+
+    #[test]
+    fn empty_maps_have_no_issues() {
+        assert!(codes(&map::Map::new((200, 200))).is_empty());
+    }
+
+    #[test]
+    fn duplicate_names_are_errors() {
+        let mut map = map::Map::new((200, 200));
+        map.obstacles.push(obstacle("o", (0, 10)));
+        map.obstacles.push(obstacle("o", (20, 10)));
+        map.obstacles.push(obstacle("player", (40, 10)));
+        assert_eq!(codes(&map), vec![
+            issue(Severity::Error, "duplicate-name", "o"), issue(Severity::Error, "duplicate-name", "player"),
+        ]);
+    }
+
+    #[test]
+    fn unknown_command_targets_are_errors() {
+        let mut map = map::Map::new((200, 200));
+        map.obstacles.push(obstacle("o", (0, 10)));
+        let mut command = HashMap::new();
+        command.insert("close".to_string(), vec![
+            vec!["remove".to_string(), "o".to_string()], vec!["remove".to_string(), "ghost".to_string()],
+        ]);
+        map.switches.push(map::Switch { name: "s".to_string(), pos: (20, 10), size: (10, 10), color: (0, 0, 0), command });
+        assert_eq!(codes(&map), vec![issue(Severity::Error, "unknown-target", "s")]);
+    }
+
+    #[test]
+    fn empty_tracks_are_errors() {
+        let mut map = map::Map::new((200, 200));
+        map.elevators.push(elevator("e", vec![]));
+        assert_eq!(codes(&map), vec![issue(Severity::Error, "empty-track", "e")]);
+    }
+
+    #[test]
+    fn unreachable_waypoints_are_errors() {
+        let mut map = map::Map::new((200, 200));
+        map.elevators.push(elevator("e", vec![
+            map::Track { pos: (0, 100), speed: (3, 0) }, map::Track { pos: (100, 100), speed: (-3, 0) },
+        ]));
+        assert_eq!(codes(&map), vec![issue(Severity::Error, "unreachable-waypoint", "e")]);
+    }
+
+    #[test]
+    fn objects_outside_the_map_are_warnings() {
+        let mut map = map::Map::new((200, 200));
+        map.obstacles.push(obstacle("o", (195, 10)));
+        map.elevators.push(elevator("e", vec![
+            map::Track { pos: (0, 100), speed: (0, 5) }, map::Track { pos: (0, 250), speed: (0, -5) },
+        ]));
+        assert_eq!(codes(&map), vec![
+            issue(Severity::Warning, "out-of-bounds", "e"), issue(Severity::Warning, "out-of-bounds", "o"),
+        ]);
+    }
+
+    #[test]
+    fn unknown_fonts_are_warnings() {
+        let mut map = map::Map::new((200, 200));
+        let description = |name: &str, family: &str| map::Description {
+            name: name.to_string(), pos: (0, 100), size: (100, 50), text: "".to_string(),
+            font: map::Font { family: family.to_string(), size: 10 }, color: (0, 0, 0),
+        };
+        map.descriptions.push(description("known", "merriweather"));
+        map.descriptions.push(description("unknown", "missing"));
+        assert_eq!(codes(&map), vec![issue(Severity::Warning, "unknown-font", "unknown")]);
+    }
+
+    #[test]
+    fn levels_that_fail_to_load_are_errors() {
+        let report = lint_file("missing-level.ron".to_string());
+        assert!(!report.ok);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].code, "parse");
+    }
+}
+
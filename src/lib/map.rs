@@ -2,16 +2,17 @@ use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use amethyst::{
     core::Parent,
-    ecs::Entity,
+    ecs::{ Entity, Join },
     prelude::*,
     ui::{ Anchor, LineMode, UiImage, UiText, UiTransform },
 };
 
-use crate::lib::components;
-use crate::lib::fonts;
-use crate::lib::utils;
+use crate::components;
+use crate::fonts;
+use crate::legacy;
+use crate::utils;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Map {
     pub size: (u32, u32),
     pub player: Player,
@@ -22,10 +23,51 @@ pub struct Map {
     pub elevators: Vec<Elevator>,
     pub obstacles: Vec<Obstacle>,
     pub descriptions: Vec<Description>,
+    #[serde(default)]
+    pub checkpoints: Vec<Checkpoint>,
+    #[serde(default = "default_lives")]
+    pub lives: u32,  // number of tries before the level is lost
+    #[serde(default)]
+    pub rewind: Option<Rewind>,  // allows rewinding time by holding R
+    #[serde(default)]
+    pub keys: Vec<Key>,
+    #[serde(default)]
+    pub doors: Vec<Door>,
+    #[serde(default)]
+    pub springs: Vec<Spring>,
+    #[serde(default)]
+    pub hazards: Vec<Hazard>,
+    #[serde(default)]
+    pub turrets: Vec<Turret>,
+    #[serde(default)]
+    pub prefabs: HashMap<String, Object>,  // templates for instances and the spawn command
+    #[serde(default)]
+    pub instances: Vec<Instance>,  // expanded into the object lists above when loading
 }
 
+fn default_lives() -> u32 { 1 }
+
 impl Map {
-    pub fn initialize(&self, mut world: &mut World) {
+    // an empty level with only the player, used by the editor
+    pub fn new(size: (u32, u32)) -> Map {
+        Map {
+            size, player: Player { pos: (50, 100), size: (30, 50), color: (0, 0, 0), health: utils::PLAYER_HEALTH },
+            targets: vec![], coins: vec![], switches: vec![], monsters: vec![], elevators: vec![], obstacles: vec![],
+            descriptions: vec![], checkpoints: vec![], lives: 1, rewind: None, keys: vec![], doors: vec![],
+            springs: vec![], hazards: vec![], turrets: vec![], prefabs: HashMap::new(), instances: vec![],
+        }
+    }
+
+    // the file name is only used in error messages
+    pub fn initialize(&self, mut world: &mut World, file_name: &String) {
+        let map_ent = self.initialize_objects(world);
+        for description in self.descriptions.iter() {
+            description.initialize(&mut world, &map_ent, file_name);
+        }
+    }
+
+    // everything except the descriptions, which need fonts and do not take part in the physics
+    pub fn initialize_objects(&self, mut world: &mut World) -> Entity {
         let map_ent = world.create_entity()
             .with(UiTransform::new(
                 "map".to_string(), Anchor::BottomLeft, Anchor::BottomLeft,
@@ -53,19 +95,522 @@ impl Map {
         for obstacle in self.obstacles.iter() {
             obstacle.initialize(&mut world, &map_ent);
         }
-        for description in self.descriptions.iter() {
-            description.initialize(&mut world, &map_ent);
+        for checkpoint in self.checkpoints.iter() {
+            checkpoint.initialize(&mut world, &map_ent);
+        }
+        for key in self.keys.iter() {
+            key.initialize(&mut world, &map_ent);
+        }
+        for door in self.doors.iter() {
+            door.initialize(&mut world, &map_ent);
+        }
+        for spring in self.springs.iter() {
+            spring.initialize(&mut world, &map_ent);
+        }
+        for hazard in self.hazards.iter() {
+            hazard.initialize(&mut world, &map_ent);
+        }
+        for turret in self.turrets.iter() {
+            turret.initialize(&mut world, &map_ent);
+        }
+        map_ent
+    }
+
+    // the tracks that objects would drift away from, with the first failing segment of each
+    pub fn check_tracks(&self) -> Vec<(String, TrackError)> {
+        let mut tracks: Vec<(&String, &Vec<Track>)> = vec![];
+        // monsters with a behavior only use the first waypoint
+        // paths are computed exactly, so only the tracks need checking
+        tracks.extend(self.monsters.iter().filter(|obj| obj.behavior.is_none() && obj.path.is_none())
+            .map(|obj| (&obj.name, &obj.track)));
+        tracks.extend(self.elevators.iter().filter(|obj| obj.path.is_none()).map(|obj| (&obj.name, &obj.track)));
+        tracks.into_iter()
+            .filter_map(|(name, track)| check_track(track).err().map(|err| (name.clone(), err)))
+            .collect()
+    }
+
+    // health only matters if something deals damage instead of killing instantly
+    pub fn uses_health(&self) -> bool {
+        self.monsters.iter().any(|m| m.damage.is_some()) || self.hazards.iter().any(|h| h.damage.is_some())
+            || self.turrets.iter().any(|t| t.projectile.damage.is_some())
+    }
+
+    // names of all objects except the player
+    pub fn object_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        names.extend(self.targets.iter().map(|obj| obj.name.clone()));
+        names.extend(self.coins.iter().map(|obj| obj.name.clone()));
+        names.extend(self.switches.iter().map(|obj| obj.name.clone()));
+        names.extend(self.monsters.iter().map(|obj| obj.name.clone()));
+        names.extend(self.elevators.iter().map(|obj| obj.name.clone()));
+        names.extend(self.obstacles.iter().map(|obj| obj.name.clone()));
+        names.extend(self.checkpoints.iter().map(|obj| obj.name.clone()));
+        names.extend(self.keys.iter().map(|obj| obj.name.clone()));
+        names.extend(self.doors.iter().map(|obj| obj.name.clone()));
+        names.extend(self.springs.iter().map(|obj| obj.name.clone()));
+        names.extend(self.hazards.iter().map(|obj| obj.name.clone()));
+        names.extend(self.turrets.iter().map(|obj| obj.name.clone()));
+        names
+    }
+
+    // instantiate a single object again (e.g. after it has been removed)
+    pub fn initialize_named(&self, name: &str, world: &mut World) {
+        let map_ent = match find_map_entity(world) {
+            Some(ent) => ent,
+            None => { return; }
+        };
+        self.targets.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.coins.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.switches.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.monsters.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.elevators.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.obstacles.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.checkpoints.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.keys.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.doors.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.springs.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.hazards.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+        self.turrets.iter().filter(|obj| obj.name == name).for_each(|obj| { obj.initialize(world, &map_ent); });
+    }
+}
+
+impl Map {
+    // add a copy of a prefab to the object lists for every instance
+    // an unknown prefab is an error, the level would silently miss objects otherwise
+    fn expand_instances(&mut self) -> Result<(), String> {
+        for instance in std::mem::take(&mut self.instances).iter() {
+            let prefab = self.prefabs.get(&instance.prefab).cloned()
+                .ok_or(format!("instance \"{}\" uses unknown prefab \"{}\"", instance.name, instance.prefab))?;
+            for (name, pos) in instance.placements().into_iter() {
+                self.add(prefab.placed(name, pos));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add(&mut self, object: Object) {
+        match object {
+            Object::Target(obj) => self.targets.push(obj),
+            Object::Coin(obj) => self.coins.push(obj),
+            Object::Switch(obj) => self.switches.push(obj),
+            Object::Monster(obj) => self.monsters.push(obj),
+            Object::Elevator(obj) => self.elevators.push(obj),
+            Object::Obstacle(obj) => self.obstacles.push(obj),
+            Object::Checkpoint(obj) => self.checkpoints.push(obj),
+            Object::Key(obj) => self.keys.push(obj),
+            Object::Door(obj) => self.doors.push(obj),
+            Object::Spring(obj) => self.springs.push(obj),
+            Object::Hazard(obj) => self.hazards.push(obj),
+            Object::Turret(obj) => self.turrets.push(obj),
+        }
+    }
+
+    // all objects except the player and the descriptions, in the order they are initialized
+    pub fn objects(&self) -> Vec<Object> {
+        let mut objects: Vec<Object> = vec![];
+        objects.extend(self.targets.iter().map(|obj| Object::Target(obj.clone())));
+        objects.extend(self.coins.iter().map(|obj| Object::Coin(obj.clone())));
+        objects.extend(self.switches.iter().map(|obj| Object::Switch(obj.clone())));
+        objects.extend(self.monsters.iter().map(|obj| Object::Monster(obj.clone())));
+        objects.extend(self.elevators.iter().map(|obj| Object::Elevator(obj.clone())));
+        objects.extend(self.obstacles.iter().map(|obj| Object::Obstacle(obj.clone())));
+        objects.extend(self.checkpoints.iter().map(|obj| Object::Checkpoint(obj.clone())));
+        objects.extend(self.keys.iter().map(|obj| Object::Key(obj.clone())));
+        objects.extend(self.doors.iter().map(|obj| Object::Door(obj.clone())));
+        objects.extend(self.springs.iter().map(|obj| Object::Spring(obj.clone())));
+        objects.extend(self.hazards.iter().map(|obj| Object::Hazard(obj.clone())));
+        objects.extend(self.turrets.iter().map(|obj| Object::Turret(obj.clone())));
+        objects
+    }
+
+    // take an object out of the level
+    pub fn remove(&mut self, name: &str) -> Option<Object> {
+        if let Some(i) = self.targets.iter().position(|obj| obj.name == name) {
+            return Some(Object::Target(self.targets.remove(i)));
+        }
+        if let Some(i) = self.coins.iter().position(|obj| obj.name == name) {
+            return Some(Object::Coin(self.coins.remove(i)));
+        }
+        if let Some(i) = self.switches.iter().position(|obj| obj.name == name) {
+            return Some(Object::Switch(self.switches.remove(i)));
+        }
+        if let Some(i) = self.monsters.iter().position(|obj| obj.name == name) {
+            return Some(Object::Monster(self.monsters.remove(i)));
+        }
+        if let Some(i) = self.elevators.iter().position(|obj| obj.name == name) {
+            return Some(Object::Elevator(self.elevators.remove(i)));
+        }
+        if let Some(i) = self.obstacles.iter().position(|obj| obj.name == name) {
+            return Some(Object::Obstacle(self.obstacles.remove(i)));
+        }
+        if let Some(i) = self.checkpoints.iter().position(|obj| obj.name == name) {
+            return Some(Object::Checkpoint(self.checkpoints.remove(i)));
+        }
+        if let Some(i) = self.keys.iter().position(|obj| obj.name == name) {
+            return Some(Object::Key(self.keys.remove(i)));
+        }
+        if let Some(i) = self.doors.iter().position(|obj| obj.name == name) {
+            return Some(Object::Door(self.doors.remove(i)));
+        }
+        if let Some(i) = self.springs.iter().position(|obj| obj.name == name) {
+            return Some(Object::Spring(self.springs.remove(i)));
+        }
+        if let Some(i) = self.hazards.iter().position(|obj| obj.name == name) {
+            return Some(Object::Hazard(self.hazards.remove(i)));
+        }
+        if let Some(i) = self.turrets.iter().position(|obj| obj.name == name) {
+            return Some(Object::Turret(self.turrets.remove(i)));
+        }
+        None
+    }
+
+    // put an object in the place of the one with the name, so that the order of the objects is kept
+    pub fn replace(&mut self, name: &str, object: Object) {
+        fn put<T>(list: &mut Vec<T>, index: Option<usize>, obj: T) {
+            match index {
+                Some(i) => { list[i] = obj; }
+                None => { list.push(obj); }
+            }
+        }
+        match object {
+            Object::Target(obj) => put(&mut self.targets, self.targets.iter().position(|o| o.name == name), obj),
+            Object::Coin(obj) => put(&mut self.coins, self.coins.iter().position(|o| o.name == name), obj),
+            Object::Switch(obj) => put(&mut self.switches, self.switches.iter().position(|o| o.name == name), obj),
+            Object::Monster(obj) => put(&mut self.monsters, self.monsters.iter().position(|o| o.name == name), obj),
+            Object::Elevator(obj) => put(&mut self.elevators, self.elevators.iter().position(|o| o.name == name), obj),
+            Object::Obstacle(obj) => put(&mut self.obstacles, self.obstacles.iter().position(|o| o.name == name), obj),
+            Object::Checkpoint(obj) => put(&mut self.checkpoints, self.checkpoints.iter().position(|o| o.name == name), obj),
+            Object::Key(obj) => put(&mut self.keys, self.keys.iter().position(|o| o.name == name), obj),
+            Object::Door(obj) => put(&mut self.doors, self.doors.iter().position(|o| o.name == name), obj),
+            Object::Spring(obj) => put(&mut self.springs, self.springs.iter().position(|o| o.name == name), obj),
+            Object::Hazard(obj) => put(&mut self.hazards, self.hazards.iter().position(|o| o.name == name), obj),
+            Object::Turret(obj) => put(&mut self.turrets, self.turrets.iter().position(|o| o.name == name), obj),
+        }
+    }
+
+    // make the commands of the switches (and of the switch prefabs) refer to a renamed object
+    pub fn rename_references(&mut self, old: &str, new: &str) {
+        let prefabs = self.prefabs.values_mut().filter_map(|prefab| match prefab {
+            Object::Switch(obj) => Some(obj),
+            _ => None,
+        });
+        for switch in self.switches.iter_mut().chain(prefabs) {
+            for commands in switch.command.values_mut() {
+                for command in commands.iter_mut() {
+                    *command = rename_target(command, old, new);
+                }
+            }
+        }
+    }
+
+    // save as RON, instances and prefab spawns are written out already expanded
+    // writes the map as it is, a loaded map has its instances and spawn commands already expanded
+    pub fn to_file(&self, file_name: &String) -> Result<(), Box<dyn std::error::Error>> {
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        std::fs::write(file_name, contents)?;
+        Ok(())
+    }
+
+    // turn ["spawn", <prefab>, <name>, "[x, y]"] into ["spawn", <object in RON>]
+    // so that the commands do not need the map to run
+    fn expand_spawns(&mut self) -> Result<(), String> {
+        let prefabs = &self.prefabs;
+        for switch in self.switches.iter_mut() {
+            for commands in switch.command.values_mut() {
+                for command in commands.iter_mut() {
+                    *command = expand_spawn(prefabs, command)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // commands run while the game plays, so a malformed one is rejected when the level loads
+    fn check_commands(&self) -> Result<(), String> {
+        for switch in self.switches.iter() {
+            for command in switch.command.values().flatten() {
+                if let Err(err) = components::Command::parse(command) {
+                    return Err(format!("command {:?} of \"{}\": {}", command, switch.name, err));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn expand_spawn(prefabs: &HashMap<String, Object>, command: &Vec<String>) -> Result<Vec<String>, String> {
+    match command.get(0).map(|name| name.as_str()) {
+        Some("spawn") if command.len() == 4 => {
+            let prefab = prefabs.get(&command[1])
+                .ok_or(format!("spawn command uses unknown prefab \"{}\"", command[1]))?;
+            let pos = serde_json::from_str::<(i32, i32)>(command[3].as_str())
+                .map_err(|err| format!("spawn command has invalid position \"{}\": {}", command[3], err))?;
+            let object = prefab.placed(command[2].clone(), pos);
+            Ok(vec!["spawn".to_string(), ron::to_string(&object).unwrap()])
+        }
+        Some("if") if command.len() == 3 => {
+            // the body of a condition is stored as JSON text, an invalid body is reported by check_commands
+            let body = match serde_json::from_str::<Vec<Vec<String>>>(command[2].as_str()) {
+                Ok(body) => body,
+                Err(_) => { return Ok(command.clone()); }
+            };
+            let body = body.iter().map(|command| expand_spawn(prefabs, command)).collect::<Result<Vec<Vec<String>>, String>>()?;
+            Ok(vec![command[0].clone(), command[1].clone(), serde_json::to_string(&body).unwrap()])
+        }
+        _ => Ok(command.clone()),
+    }
+}
+
+// the command with the references to an object renamed, also inside conditions and spawned switches
+fn rename_target(command: &Vec<String>, old: &str, new: &str) -> Vec<String> {
+    let mut command = command.clone();
+    match command.get(0).map(|name| name.as_str()) {
+        Some("remove") | Some("object") if command.len() > 1 => {
+            if command[1] == old { command[1] = new.to_string(); }
+        }
+        Some("spawn") if command.len() == 2 => {
+            // only written again if it changes, so that an untouched object keeps its text
+            if let Ok(Object::Switch(mut switch)) = ron::from_str::<Object>(command[1].as_str()) {
+                let before = switch.command.clone();
+                for commands in switch.command.values_mut() {
+                    for cmd in commands.iter_mut() { *cmd = rename_target(cmd, old, new); }
+                }
+                if switch.command != before {
+                    command[1] = ron::to_string(&Object::Switch(switch)).unwrap();
+                }
+            }
+        }
+        Some("if") if command.len() == 3 => {
+            // the condition and the body are stored as JSON text, an invalid one is reported by check_commands
+            if let Ok(condition) = serde_json::from_str::<Vec<String>>(command[1].as_str()) {
+                let renamed = rename_target(&condition, old, new);
+                if renamed != condition { command[1] = serde_json::to_string(&renamed).unwrap(); }
+            }
+            if let Ok(body) = serde_json::from_str::<Vec<Vec<String>>>(command[2].as_str()) {
+                let renamed: Vec<Vec<String>> = body.iter().map(|cmd| rename_target(cmd, old, new)).collect();
+                if renamed != body { command[2] = serde_json::to_string(&renamed).unwrap(); }
+            }
+        }
+        _ => {}
+    }
+    command
+}
+
+// A prefab placed in the level, only the name and the position are given
+// with a repeat, one object is placed per cell and the index is appended to the name (c0, c1, ...)
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Instance {
+    pub prefab: String,
+    pub name: String,
+    pub pos: (i32, i32),
+    #[serde(default)]
+    pub repeat: Option<Repeat>,
+}
+
+// A row (rows: 1) or a grid of instances
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Repeat {
+    pub columns: u32,
+    #[serde(default = "default_rows")]
+    pub rows: u32,
+    pub step: (i32, i32),  // distance between neighbouring cells
+}
+
+fn default_rows() -> u32 { 1 }
+
+impl Instance {
+    // the names and positions of all objects of the instance, row by row
+    fn placements(&self) -> Vec<(String, (i32, i32))> {
+        let repeat = match &self.repeat {
+            Some(repeat) => repeat,
+            None => { return vec![(self.name.clone(), self.pos)]; }
+        };
+        let mut result = vec![];
+        for row in 0..repeat.rows {
+            for column in 0..repeat.columns {
+                let index = row * repeat.columns + column;
+                let pos = (
+                    self.pos.0 + column as i32 * repeat.step.0,
+                    self.pos.1 + row as i32 * repeat.step.1);
+                result.push((self.name.clone() + &index.to_string(), pos));
+            }
+        }
+        result
+    }
+}
+
+// Any object that can be placed from a prefab
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum Object {
+    Target(Target),
+    Coin(Coin),
+    Switch(Switch),
+    Monster(Monster),
+    Elevator(Elevator),
+    Obstacle(Obstacle),
+    Checkpoint(Checkpoint),
+    Key(Key),
+    Door(Door),
+    Spring(Spring),
+    Hazard(Hazard),
+    Turret(Turret),
+}
+
+impl Object {
+    pub fn name(&self) -> &String {
+        match self {
+            Object::Target(obj) => &obj.name,
+            Object::Coin(obj) => &obj.name,
+            Object::Switch(obj) => &obj.name,
+            Object::Monster(obj) => &obj.name,
+            Object::Elevator(obj) => &obj.name,
+            Object::Obstacle(obj) => &obj.name,
+            Object::Checkpoint(obj) => &obj.name,
+            Object::Key(obj) => &obj.name,
+            Object::Door(obj) => &obj.name,
+            Object::Spring(obj) => &obj.name,
+            Object::Hazard(obj) => &obj.name,
+            Object::Turret(obj) => &obj.name,
+        }
+    }
+
+    // a copy with another name at another position, tracks are moved along
+    pub fn placed(&self, name: String, pos: (i32, i32)) -> Object {
+        let mut object = self.clone();
+        let move_track = |track: &mut Vec<Track>, path: &mut Option<Path>| {
+            let start = start_pos(track, path);
+            let shift = |p: (i32, i32)| (p.0 - start.0 + pos.0, p.1 - start.1 + pos.1);
+            for tr in track.iter_mut() { tr.pos = shift(tr.pos); }
+            if let Some(path) = path {
+                for wp in path.waypoints.iter_mut() { wp.pos = shift(wp.pos); }
+            }
+        };
+        match &mut object {
+            Object::Target(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Coin(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Switch(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Monster(obj) => { obj.name = name; move_track(&mut obj.track, &mut obj.path); }
+            Object::Elevator(obj) => { obj.name = name; move_track(&mut obj.track, &mut obj.path); }
+            Object::Obstacle(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Checkpoint(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Key(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Door(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Spring(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Hazard(obj) => { obj.name = name; obj.pos = pos; }
+            Object::Turret(obj) => { obj.name = name; obj.pos = pos; }
+        }
+        object
+    }
+
+    // the top left corner, for movables the start of the track
+    pub fn pos(&self) -> (i32, i32) {
+        match self {
+            Object::Target(obj) => obj.pos,
+            Object::Coin(obj) => obj.pos,
+            Object::Switch(obj) => obj.pos,
+            Object::Monster(obj) => start_pos(&obj.track, &obj.path),
+            Object::Elevator(obj) => start_pos(&obj.track, &obj.path),
+            Object::Obstacle(obj) => obj.pos,
+            Object::Checkpoint(obj) => obj.pos,
+            Object::Key(obj) => obj.pos,
+            Object::Door(obj) => obj.pos,
+            Object::Spring(obj) => obj.pos,
+            Object::Hazard(obj) => obj.pos,
+            Object::Turret(obj) => obj.pos,
+        }
+    }
+
+    // the size and the color
+    pub fn style(&self) -> ((u32, u32), (u32, u32, u32)) {
+        match self {
+            Object::Target(obj) => (obj.size, obj.color),
+            Object::Coin(obj) => (obj.size, obj.color),
+            Object::Switch(obj) => (obj.size, obj.color),
+            Object::Monster(obj) => (obj.size, obj.color),
+            Object::Elevator(obj) => (obj.size, obj.color),
+            Object::Obstacle(obj) => (obj.size, obj.color),
+            Object::Checkpoint(obj) => (obj.size, obj.color),
+            Object::Key(obj) => (obj.size, obj.color),
+            Object::Door(obj) => (obj.size, obj.color),
+            Object::Spring(obj) => (obj.size, obj.color),
+            Object::Hazard(obj) => (obj.size, obj.color),
+            Object::Turret(obj) => (obj.size, obj.color),
         }
     }
+
+    // a copy with another size and color
+    pub fn restyled(&self, size: (u32, u32), color: (u32, u32, u32)) -> Object {
+        let mut object = self.clone();
+        let (obj_size, obj_color) = object.style_mut();
+        *obj_size = size;
+        *obj_color = color;
+        object
+    }
+
+    fn style_mut(&mut self) -> (&mut (u32, u32), &mut (u32, u32, u32)) {
+        match self {
+            Object::Target(obj) => (&mut obj.size, &mut obj.color),
+            Object::Coin(obj) => (&mut obj.size, &mut obj.color),
+            Object::Switch(obj) => (&mut obj.size, &mut obj.color),
+            Object::Monster(obj) => (&mut obj.size, &mut obj.color),
+            Object::Elevator(obj) => (&mut obj.size, &mut obj.color),
+            Object::Obstacle(obj) => (&mut obj.size, &mut obj.color),
+            Object::Checkpoint(obj) => (&mut obj.size, &mut obj.color),
+            Object::Key(obj) => (&mut obj.size, &mut obj.color),
+            Object::Door(obj) => (&mut obj.size, &mut obj.color),
+            Object::Spring(obj) => (&mut obj.size, &mut obj.color),
+            Object::Hazard(obj) => (&mut obj.size, &mut obj.color),
+            Object::Turret(obj) => (&mut obj.size, &mut obj.color),
+        }
+    }
+
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        match self {
+            Object::Target(obj) => obj.initialize(world, parent),
+            Object::Coin(obj) => obj.initialize(world, parent),
+            Object::Switch(obj) => obj.initialize(world, parent),
+            Object::Monster(obj) => obj.initialize(world, parent),
+            Object::Elevator(obj) => obj.initialize(world, parent),
+            Object::Obstacle(obj) => obj.initialize(world, parent),
+            Object::Checkpoint(obj) => obj.initialize(world, parent),
+            Object::Key(obj) => obj.initialize(world, parent),
+            Object::Door(obj) => obj.initialize(world, parent),
+            Object::Spring(obj) => obj.initialize(world, parent),
+            Object::Hazard(obj) => obj.initialize(world, parent),
+            Object::Turret(obj) => obj.initialize(world, parent),
+        }
+    }
+}
+
+// create an object while playing, parented to the map like the objects of the level
+// it is marked with a SpawnedComp so that snapshots know it is not part of the level
+pub fn spawn(world: &mut World, object: &Object) {
+    let map_ent = match find_map_entity(world) {
+        Some(ent) => ent,
+        None => { return; }
+    };
+    let ent = object.initialize(world, &map_ent);
+    world.write_storage::<components::SpawnedComp>()
+        .insert(ent, components::SpawnedComp::new(object.clone())).unwrap();
+}
+
+// the entity all objects of the map are parented to
+pub fn find_map_entity(world: &World) -> Option<Entity> {
+    let map_store = world.read_storage::<components::MapComp>();
+    (&world.entities(), &map_store).join().map(|(ent, _)| ent).next()
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Player {
     pub pos: (i32, i32),
     pub size: (u32, u32),
     pub color: (u32, u32, u32),
+    #[serde(default = "default_health")]
+    pub health: u32,
 }
 
+fn default_health() -> u32 { utils::PLAYER_HEALTH }
+
 impl Player {
     pub fn initialize(&self, world: &mut World, parent: &Entity) {
         world.create_entity()
@@ -76,13 +621,13 @@ impl Player {
             .with(UiImage::SolidColor(
                 utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
             .with(components::ObjectComp::new("player".to_string(), components::ObjectType::Player))
-            .with(components::PlayerComp::new("player".to_string()))
+            .with(components::PlayerComp::new("player".to_string(), self.health))
             .with(Parent::new(*parent))
             .build();
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Target {
     pub name: String,
     pub pos: (i32, i32),
@@ -91,7 +636,7 @@ pub struct Target {
 }
 
 impl Target {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
         world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
@@ -102,11 +647,11 @@ impl Target {
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Target))
             .with(components::InteractableComp::new(self.name.clone(), HashMap::new()))
             .with(Parent::new(*parent))
-            .build();
+            .build()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Coin {
     pub name: String,
     pub pos: (i32, i32),
@@ -115,7 +660,7 @@ pub struct Coin {
 }
 
 impl Coin {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
         world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
@@ -126,11 +671,11 @@ impl Coin {
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Coin))
             .with(components::InteractableComp::new(self.name.clone(), HashMap::new()))
             .with(Parent::new(*parent))
-            .build();
+            .build()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Switch {
     pub name: String,
     pub pos: (i32, i32),
@@ -140,7 +685,7 @@ pub struct Switch {
 }
 
 impl Switch {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
         world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
@@ -151,72 +696,109 @@ impl Switch {
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Switch))
             .with(components::InteractableComp::new(self.name.clone(), self.command.clone()))
             .with(Parent::new(*parent))
-            .build();
+            .build()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Monster {
     pub name: String,
+    #[serde(default)]
     pub track: Vec<Track>,
+    #[serde(default)]
+    pub path: Option<Path>,  // replaces the track
     pub size: (u32, u32),
     pub color: (u32, u32, u32),
+    #[serde(default)]
+    pub damage: Option<u32>,  // kills instantly if not set
+    #[serde(default)]
+    pub stompable: bool,  // can be defeated by landing on it
+    #[serde(default)]
+    pub behavior: Option<Behavior>,  // replaces the track or path, which then only gives the starting point
 }
 
 impl Monster {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
-        world.create_entity()
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        let start = start_pos(&self.track, &self.path);
+        let ent = world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
-                self.track[0].pos.0 as f32 * utils::dpi(),
-                self.track[0].pos.1 as f32 * utils::dpi(), 0.5,
+                start.0 as f32 * utils::dpi(), start.1 as f32 * utils::dpi(), 0.5,
                 self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
             .with(UiImage::SolidColor(
                 utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Monster))
-            .with(components::MovableComp::new(self.name.clone(), self.track.clone()))
             .with(components::InteractableComp::new(self.name.clone(), HashMap::new()))
+            .with(components::DamageComp::new(self.name.clone(), self.damage, self.stompable))
             .with(Parent::new(*parent))
             .build();
+
+        // monsters either follow their track or act on their own
+        match &self.behavior {
+            Some(behavior) => {
+                world.write_storage::<components::EnemyComp>()
+                    .insert(ent, components::EnemyComp::new(self.name.clone(), behavior.clone())).unwrap();
+            }
+            None => {
+                world.write_storage::<components::MovableComp>()
+                    .insert(ent, components::MovableComp::new(self.name.clone(), self.track.clone(), self.path.clone())).unwrap();
+            }
+        }
+        ent
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Enemy behaviors, speeds are per tick and radii are in unscaled pixels
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub enum Behavior {
+    Patrol { speed: i32 },  // walks back and forth, turning at walls and ledges
+    Chase { speed: i32, radius: u32 },  // walks toward the player when close enough
+    Jump { speed: i32, jump: i32, radius: u32 },  // jumps toward the player when close enough
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Elevator {
     pub name: String,
+    #[serde(default)]
     pub track: Vec<Track>,
+    #[serde(default)]
+    pub path: Option<Path>,  // replaces the track
     pub size: (u32, u32),
     pub color: (u32, u32, u32),
+    #[serde(default)]
+    pub one_way: bool,  // can be jumped through from below
 }
 
 impl Elevator {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        let start = start_pos(&self.track, &self.path);
         world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
-                self.track[0].pos.0 as f32 * utils::dpi(),
-                self.track[0].pos.1 as f32 * utils::dpi(), 0.3,
+                start.0 as f32 * utils::dpi(), start.1 as f32 * utils::dpi(), 0.3,
                 self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
             .with(UiImage::SolidColor(
                 utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Elevator))
-            .with(components::MovableComp::new(self.name.clone(), self.track.clone()))
-            .with(components::CollidableComp::new(self.name.clone()))
+            .with(components::MovableComp::new(self.name.clone(), self.track.clone(), self.path.clone()))
+            .with(components::CollidableComp::new(self.name.clone(), self.one_way))
             .with(Parent::new(*parent))
-            .build();
+            .build()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Obstacle {
     pub name: String,
     pub pos: (i32, i32),
     pub size: (u32, u32),
     pub color: (u32, u32, u32),
+    #[serde(default)]
+    pub one_way: bool,  // can be jumped through from below
 }
 
 impl Obstacle {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
         world.create_entity()
             .with(UiTransform::new(
                 self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
@@ -225,13 +807,13 @@ impl Obstacle {
             .with(UiImage::SolidColor(
                 utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
             .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Obstacle))
-            .with(components::CollidableComp::new(self.name.clone()))
+            .with(components::CollidableComp::new(self.name.clone(), self.one_way))
             .with(Parent::new(*parent))
-            .build();
+            .build()
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Description {
     pub name: String,
     pub pos: (i32, i32),
@@ -242,7 +824,11 @@ pub struct Description {
 }
 
 impl Description {
-    pub fn initialize(&self, world: &mut World, parent: &Entity) {
+    pub fn initialize(&self, world: &mut World, parent: &Entity, file_name: &String) {
+        if fonts::Fonts::instance().resolve(&self.font.family).is_none() {
+            log::error!("Level \"{}\": description \"{}\" uses unknown font family \"{}\"",
+                file_name, self.name, self.font.family);
+        }
         let font = fonts::Fonts::instance().get(self.font.family.clone(), world);
 
         world.create_entity()
@@ -261,20 +847,669 @@ impl Description {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    pub active_color: (u32, u32, u32),
+}
+
+impl Checkpoint {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        // the color changes once the checkpoint is reached
+        let mut command = HashMap::new();
+        command.insert("close".to_string(), vec![
+            vec!["state".to_string(), "open".to_string()],
+            vec!["color".to_string(), self.active_color.0.to_string(),
+                self.active_color.1.to_string(), self.active_color.2.to_string()],
+        ]);
+
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.25,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Checkpoint))
+            .with(components::InteractableComp::new(self.name.clone(), command))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Key {
+    pub name: String,
+    #[serde(default)]
+    pub id: String,  // opens the doors with the same id, or with the same color if empty
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+}
+
+impl Key {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.6,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Key))
+            .with(components::InteractableComp::new(self.name.clone(), HashMap::new()))
+            .with(components::LockComp::new(self.name.clone(), lock_id(&self.id, self.color)))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Door {
+    pub name: String,
+    #[serde(default)]
+    pub id: String,  // the id of the key that opens it, or match the key by color if empty
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+}
+
+impl Door {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.15,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Door))
+            .with(components::CollidableComp::new(self.name.clone(), false))
+            .with(components::LockComp::new(self.name.clone(), lock_id(&self.id, self.color)))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Spring {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    pub impulse: (i32, i32),  // the speed given to the player, a sideways part makes a launcher
+    #[serde(default)]
+    pub cooldown: u32,  // in ticks
+    #[serde(default)]
+    pub active_color: Option<(u32, u32, u32)>,  // shown while cooling down
+}
+
+impl Spring {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        let color = |c: (u32, u32, u32)| vec![
+            "color".to_string(), c.0.to_string(), c.1.to_string(), c.2.to_string()];
+        let mut command = HashMap::new();
+        command.insert("launch".to_string(), vec![
+            color(self.active_color.unwrap_or(self.color)), vec!["state".to_string(), "cooldown".to_string()],
+        ]);
+        command.insert("ready".to_string(), vec![
+            color(self.color), vec!["state".to_string(), "close".to_string()],
+        ]);
+
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.15,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Spring))
+            .with(components::CollidableComp::new(self.name.clone(), false))
+            .with(components::InteractableComp::new(self.name.clone(), command))
+            .with(components::SpringComp::new(self.name.clone(), self.impulse, self.cooldown))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+// Hazards: stationary areas that hurt the player (lava, spikes, ...)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Hazard {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    #[serde(default)]
+    pub damage: Option<u32>,  // kills instantly if not set
+}
+
+impl Hazard {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.5,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Hazard))
+            .with(components::InteractableComp::new(self.name.clone(), HashMap::new()))
+            .with(components::DamageComp::new(self.name.clone(), self.damage, false))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Turret {
+    pub name: String,
+    pub pos: (i32, i32),
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    pub interval: u32,  // ticks between shots
+    #[serde(default)]
+    pub sight: Option<u32>,  // if set, only fire at a player in line of sight within this distance
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,  // can be changed with ["object", <name>, "enabled", "True"/"False"]
+    pub projectile: Projectile,
+}
+
+fn default_enabled() -> bool { true }
+
+impl Turret {
+    pub fn initialize(&self, world: &mut World, parent: &Entity) -> Entity {
+        world.create_entity()
+            .with(UiTransform::new(
+                self.name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                self.pos.0 as f32 * utils::dpi(), self.pos.1 as f32 * utils::dpi(), 0.35,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(self.name.clone(), components::ObjectType::Turret))
+            .with(components::CollidableComp::new(self.name.clone(), false))
+            .with(components::TurretComp::new(
+                self.name.clone(), self.projectile.clone(), self.interval, self.sight, self.enabled))
+            .with(Parent::new(*parent))
+            .build()
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Projectile {
+    pub size: (u32, u32),
+    pub color: (u32, u32, u32),
+    pub speed: (i32, i32),
+    pub lifetime: u32,  // in ticks
+    #[serde(default)]
+    pub damage: Option<u32>,  // kills instantly if not set
+}
+
+impl Projectile {
+    // projectiles only exist while playing, so they are not listed in the map
+    pub fn spawn(&self, world: &mut World, parent: &Entity, name: String, turret: String, pos: (i32, i32), lifetime: u32) {
+        world.create_entity()
+            .with(UiTransform::new(
+                name.clone(), Anchor::BottomLeft, Anchor::TopLeft,
+                pos.0 as f32 * utils::dpi(), pos.1 as f32 * utils::dpi(), 0.55,
+                self.size.0 as f32 * utils::dpi(), self.size.1 as f32 * utils::dpi()))
+            .with(UiImage::SolidColor(
+                utils::get_color([self.color.0, self.color.1, self.color.2, 255])))
+            .with(components::ObjectComp::new(name.clone(), components::ObjectType::Projectile))
+            .with(components::InteractableComp::new(name.clone(), HashMap::new()))
+            .with(components::DamageComp::new(name.clone(), self.damage, false))
+            .with(components::ProjectileComp::new(name, turret, self.clone(), lifetime))
+            .with(Parent::new(*parent))
+            .build();
+    }
+}
+
+// keys and doors without an id are matched by color, written like "#ff0000"
+pub fn lock_id(id: &String, color: (u32, u32, u32)) -> String {
+    if !id.is_empty() { return id.clone(); }
+    format!("#{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Track {
     pub pos: (i32, i32),
     pub speed: (i32, i32),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+// Why an object leaves its track, the segment starts at the waypoint with the given index
+#[derive(Debug)]
+pub struct TrackError {
+    pub segment: usize,
+    pub pos: (i32, i32),
+    pub reason: String,
+}
+
+impl std::fmt::Display for TrackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "segment from waypoint {} at {:?} {}", self.segment, self.pos, self.reason)
+    }
+}
+
+impl std::error::Error for TrackError {}
+
+// the number of ticks until moving from the position with the speed lands exactly on the target
+fn ticks_to(from: (i32, i32), speed: (i32, i32), to: (i32, i32)) -> Option<i32> {
+    let axis = |d: i32, s: i32| match s {
+        0 if d == 0 => Some(None),  // any number of ticks
+        0 => None,
+        _ if d % s == 0 && d / s > 0 => Some(Some(d / s)),
+        _ => None,
+    };
+    match (axis(to.0 - from.0, speed.0), axis(to.1 - from.1, speed.1)) {
+        (Some(Some(x)), Some(Some(y))) if x == y => Some(x),
+        (Some(Some(t)), Some(None)) | (Some(None), Some(Some(t))) => Some(t),
+        _ => None,
+    }
+}
+
+// follow the track from the first waypoint the way MovableComp::move_ does
+// the object has to stop (speed zero, e.g. to wait for a switch) or loop back to where it started moving,
+// passing every waypoint
+// a command is expected to send a stopped object on to the next waypoint in the list, so the check goes on from there
+pub fn check_track(track: &Vec<Track>) -> Result<(), TrackError> {
+    if track.is_empty() {
+        return Err(TrackError { segment: 0, pos: (0, 0), reason: "has no waypoint".to_string() });
+    }
+    let mut pos = track[0].pos;
+    let mut start = 0;  // where the object started moving after the last stop
+    let mut visited: Vec<usize> = vec![];
+    loop {
+        // like move_, the speed comes from the first waypoint at the position
+        let index = track.iter().position(|tr| tr.pos == pos).unwrap();
+        let speed = track[index].speed;
+        if speed == (0, 0) {
+            if !visited.contains(&index) { visited.push(index); }
+            match track.get(index + 1) {
+                Some(next) if !visited.contains(&(index + 1)) => {
+                    start = index + 1;
+                    pos = next.pos;
+                    continue;
+                }
+                _ => { break; }
+            }
+        }
+
+        // the loop has to end where the object started moving, not cycle between later waypoints
+        if visited.contains(&index) {
+            if index == start { break; }
+            let last = *visited.last().unwrap();
+            return Err(TrackError {
+                segment: last, pos: track[last].pos,
+                reason: format!("returns to waypoint {} at {:?} instead of waypoint {}", index, track[index].pos, start),
+            });
+        }
+        visited.push(index);
+
+        // the next waypoint passed exactly is where the speed changes
+        let hit = track.iter()
+            .filter_map(|tr| ticks_to(pos, speed, tr.pos).map(|ticks| (ticks, tr.pos)))
+            .min();
+        match hit {
+            Some((_, next)) => { pos = next; }
+            None => {
+                return Err(TrackError {
+                    segment: index, pos,
+                    reason: format!("never reaches another waypoint with speed {:?}", speed),
+                });
+            }
+        }
+    }
+
+    // every waypoint has to be on the way
+    for (index, tr) in track.iter().enumerate() {
+        if !visited.iter().any(|other| track[*other].pos == tr.pos) {
+            let last = *visited.last().unwrap();
+            return Err(TrackError {
+                segment: last, pos: track[last].pos,
+                reason: format!("stops or loops before waypoint {} at {:?} is reached", index, tr.pos),
+            });
+        }
+    }
+    Ok(())
+}
+
+// where a movable starts, the first waypoint of its path or track
+pub fn start_pos(track: &Vec<Track>, path: &Option<Path>) -> (i32, i32) {
+    match path {
+        Some(path) => path.waypoints.first().map_or((0, 0), |wp| wp.pos),
+        None => track.first().map_or((0, 0), |tr| tr.pos),
+    }
+}
+
+// Path: waypoints with travel times instead of per-tick speeds
+// positions are computed from the tick count, so the waypoints are always hit exactly
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Path {
+    pub waypoints: Vec<Waypoint>,
+    #[serde(default)]
+    pub easing: Easing,
+    #[serde(default)]
+    pub mode: LoopMode,
+}
+
+// the time to the next waypoint is given either as a duration or as a speed
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Waypoint {
+    pub pos: (i32, i32),
+    #[serde(default)]
+    pub duration: Option<u32>,  // in ticks
+    #[serde(default)]
+    pub speed: Option<f32>,  // in unscaled pixels per tick
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum Easing { Linear, EaseInOut, Sine }
+
+impl Default for Easing {
+    fn default() -> Self { Easing::Linear }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub enum LoopMode {
+    Loop,  // from the last waypoint back to the first
+    PingPong,  // back and forth
+    Once,  // stop at the last waypoint
+}
+
+impl Default for LoopMode {
+    fn default() -> Self { LoopMode::Loop }
+}
+
+impl Easing {
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => if t < 0.5 { 2. * t * t } else { 1. - 2. * (1. - t) * (1. - t) },
+            Easing::Sine => 0.5 - 0.5 * (t * std::f32::consts::PI).cos(),
+        }
+    }
+}
+
+impl Path {
+    // ticks to go from one waypoint to another, at least one
+    fn duration(&self, from: usize, to: usize) -> u32 {
+        let wp = &self.waypoints[from];
+        let target = self.waypoints[to].pos;
+        let duration = match (wp.duration, wp.speed) {
+            (Some(duration), _) => duration,
+            (None, Some(speed)) if speed > 0. => {
+                // in f32, the squared distance of far apart waypoints does not fit into an i32
+                let distance = (target.0 as f32 - wp.pos.0 as f32).hypot(target.1 as f32 - wp.pos.1 as f32);
+                (distance / speed).ceil() as u32
+            }
+            _ => 1,
+        };
+        duration.max(1)
+    }
+
+    // the waypoints visited in one cycle, as (from, to) pairs
+    fn segments(&self) -> Vec<(usize, usize)> {
+        let count = self.waypoints.len();
+        let forward = (0..count.saturating_sub(1)).map(|i| (i, i + 1));
+        match self.mode {
+            LoopMode::Loop if count > 1 => forward.chain(std::iter::once((count - 1, 0))).collect(),
+            LoopMode::PingPong => forward.clone().chain(forward.rev().map(|(a, b)| (b, a))).collect(),
+            _ => forward.collect(),
+        }
+    }
+
+    // the position after the number of ticks
+    pub fn position_at(&self, tick: u32) -> (i32, i32) {
+        let segments = self.segments();
+        let durations: Vec<u32> = segments.iter().map(|(from, to)| self.duration(*from, *to)).collect();
+        let total: u32 = durations.iter().sum();
+        if total == 0 || (self.mode == LoopMode::Once && tick >= total) {
+            return self.waypoints.last().map_or((0, 0), |wp| wp.pos);
+        }
+
+        let mut t = tick % total;
+        for ((from, to), duration) in segments.iter().zip(durations.iter()) {
+            if t < *duration {
+                let f = self.easing.apply(t as f32 / *duration as f32);
+                let (a, b) = (self.waypoints[*from].pos, self.waypoints[*to].pos);
+                return (
+                    a.0 + ((b.0 as f32 - a.0 as f32) * f).round() as i32,
+                    a.1 + ((b.1 as f32 - a.1 as f32) * f).round() as i32);
+            }
+            t -= duration;
+        }
+        self.waypoints.last().unwrap().pos
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Rewind {
+    pub seconds: u32,  // how far back the player can go
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Font {
     pub family: String,
     pub size: u32,
 }
 
+// like from_file, but a track that does not loop is an error instead of a warning
+pub fn from_file_strict(file_name: String) -> Result<Map, Box<dyn std::error::Error>> {
+    let map = from_file(file_name)?;
+    match map.check_tracks().into_iter().next() {
+        Some((name, err)) => Err(format!("track of \"{}\": {}", name, err).into()),
+        None => Ok(map),
+    }
+}
+
 pub fn from_file(file_name: String) -> Result<Map, Box<dyn std::error::Error>> {
+    let map = parse_file(&file_name)?;
+    for (name, err) in map.check_tracks().iter() {
+        log::warn!("Level \"{}\": track of \"{}\": {}", file_name, name, err);
+    }
+    Ok(map)
+}
+
+fn parse_file(file_name: &String) -> Result<Map, Box<dyn std::error::Error>> {
     let contents = std::fs::read_to_string(file_name)?;
-    let obj = ron::from_str(contents.as_str())?;
+    // levels 4 and above still use the legacy JSON format
+    if file_name.ends_with(".json") {
+        let level: legacy::Level = serde_json::from_str(contents.as_str())?;
+        let map = level.into_map();
+        map.check_commands()?;
+        return Ok(map);
+    }
+    let mut obj: Map = ron::from_str(contents.as_str())?;
+    obj.expand_instances()?;
+    obj.expand_spawns()?;
+    obj.check_commands()?;
     Ok(obj)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // write a map to a temporary file and load it like a level
+    fn load(name: &str, map: &Map) -> Result<Map, Box<dyn std::error::Error>> {
+        let path = std::env::temp_dir().join(format!("platformer-{}-{}.ron", name, std::process::id()))
+            .into_os_string().into_string().unwrap();
+        map.to_file(&path)?;
+        let result = from_file(path.clone());
+        std::fs::remove_file(&path).unwrap_or(());
+        result
+    }
+
+    fn coin(name: &str) -> Object {
+        Object::Coin(Coin { name: name.to_string(), pos: (0, 0), size: (10, 10), color: (255, 255, 0) })
+    }
+
+    #[test]
+    fn instances_are_expanded() {
+        let mut map = Map::new((200, 100));
+        map.prefabs.insert("coin".to_string(), coin("coin"));
+        map.instances.push(Instance {
+            prefab: "coin".to_string(), name: "c".to_string(), pos: (10, 20),
+            repeat: Some(Repeat { columns: 3, rows: 1, step: (20, 0) }),
+        });
+        let names: Vec<String> = load("instances", &map).unwrap().coins.into_iter().map(|coin| coin.name).collect();
+        assert_eq!(names, vec!["c0", "c1", "c2"]);
+    }
+
+    #[test]
+    fn replaced_objects_keep_their_place() {
+        let mut map = Map::new((200, 100));
+        for name in ["a", "b", "c"] { map.add(coin(name)); }
+        map.replace("b", coin("b").placed("d".to_string(), (5, 5)));
+        let coins: Vec<(String, (i32, i32))> = map.coins.iter().map(|coin| (coin.name.clone(), coin.pos)).collect();
+        assert_eq!(coins, vec![("a".to_string(), (0, 0)), ("d".to_string(), (5, 5)), ("c".to_string(), (0, 0))]);
+    }
+
+    #[test]
+    fn renames_reach_into_commands() {
+        let strings = |command: &[&str]| command.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+        let inner = Switch {
+            name: "s2".to_string(), pos: (0, 0), size: (10, 10), color: (0, 0, 0),
+            command: HashMap::from([("press".to_string(), vec![strings(&["remove", "door"])])]),
+        };
+        let commands = vec![
+            strings(&["remove", "door"]),
+            strings(&["object", "door", "enabled", "false"]),
+            strings(&["remove", "other"]),
+            strings(&["if", r#"["object","door","pos","[0,0]"]"#, r#"[["remove","door"]]"#]),
+            vec!["spawn".to_string(), ron::to_string(&Object::Switch(inner.clone())).unwrap()],
+        ];
+        let mut map = Map::new((200, 100));
+        map.switches.push(Switch { name: "s1".to_string(), command: HashMap::from([("press".to_string(), commands)]), ..inner });
+        map.rename_references("door", "gate");
+
+        let renamed = &map.switches[0].command["press"];
+        assert_eq!(renamed[0], strings(&["remove", "gate"]));
+        assert_eq!(renamed[1], strings(&["object", "gate", "enabled", "false"]));
+        assert_eq!(renamed[2], strings(&["remove", "other"]));
+        assert_eq!(renamed[3], strings(&["if", r#"["object","gate","pos","[0,0]"]"#, r#"[["remove","gate"]]"#]));
+        match ron::from_str::<Object>(renamed[4][1].as_str()).unwrap() {
+            Object::Switch(switch) => assert_eq!(switch.command["press"], vec![strings(&["remove", "gate"])]),
+            object => panic!("spawned {:?}", object),
+        }
+    }
+
+    fn track(waypoints: &[((i32, i32), (i32, i32))]) -> Vec<Track> {
+        waypoints.iter().map(|(pos, speed)| Track { pos: *pos, speed: *speed }).collect()
+    }
+
+    #[test]
+    fn tracks_that_loop_pass() {
+        let square = track(&[((0, 0), (2, 0)), ((10, 0), (0, 2)), ((10, 10), (-2, 0)), ((0, 10), (0, -2))]);
+        assert!(check_track(&square).is_ok());
+        let waiting = track(&[((0, 0), (2, 0)), ((10, 0), (0, 0))]);
+        assert!(check_track(&waiting).is_ok());
+    }
+
+    #[test]
+    fn tracks_are_followed_past_stops() {
+        // e.g. an elevator waiting at the bottom until a switch sends it up to a loop
+        let lift = track(&[((0, 0), (0, 0)), ((0, 10), (0, 2)), ((0, 20), (0, -2))]);
+        assert!(check_track(&lift).is_ok());
+        let stops = track(&[((0, 0), (0, 0)), ((0, 10), (0, 0)), ((0, 20), (0, 0))]);
+        assert!(check_track(&stops).is_ok());
+        let back_to_the_stop = track(&[((10, 0), (0, 0)), ((0, 0), (2, 0))]);
+        assert!(check_track(&back_to_the_stop).is_ok());
+
+        let drifting = track(&[((0, 0), (0, 0)), ((0, 10), (0, 3)), ((0, 20), (0, -2))]);
+        let err = check_track(&drifting).unwrap_err();
+        assert_eq!((err.segment, err.pos), (1, (0, 10)));
+        let skipping = track(&[((0, 0), (0, 0)), ((0, 10), (0, 2)), ((0, 20), (0, -2)), ((5, 5), (0, 0))]);
+        assert!(check_track(&skipping).is_err());
+    }
+
+    #[test]
+    fn the_shipped_levels_have_no_drifting_tracks() {
+        for file in std::fs::read_dir("assets/levels").unwrap() {
+            let file = file.unwrap().path().into_os_string().into_string().unwrap();
+            if file.ends_with("manifest.ron") { continue; }
+            let map = from_file(file.clone()).unwrap();
+            assert!(map.check_tracks().is_empty(), "{}: {:?}", file, map.check_tracks());
+        }
+    }
+
+    #[test]
+    fn tracks_that_drift_fail() {
+        let drifting = track(&[((0, 0), (3, 0)), ((10, 0), (-2, 0))]);
+        let err = check_track(&drifting).unwrap_err();
+        assert_eq!((err.segment, err.pos), (0, (0, 0)));
+    }
+
+    #[test]
+    fn tracks_that_skip_waypoints_fail() {
+        let skipping = track(&[((0, 0), (2, 0)), ((10, 0), (-2, 0)), ((10, 10), (0, -2))]);
+        assert!(check_track(&skipping).is_err());
+        assert!(check_track(&vec![]).is_err());
+    }
+
+    #[test]
+    fn tracks_that_do_not_return_to_the_start_fail() {
+        let lasso = track(&[((0, 0), (2, 0)), ((10, 0), (0, 2)), ((10, 10), (0, -2))]);
+        let err = check_track(&lasso).unwrap_err();
+        assert_eq!(err.segment, 2);
+    }
+
+    fn path(waypoints: &[(i32, i32)], easing: Easing, mode: LoopMode) -> Path {
+        Path {
+            waypoints: waypoints.iter().map(|pos| Waypoint { pos: *pos, duration: Some(10), speed: None }).collect(),
+            easing, mode,
+        }
+    }
+
+    #[test]
+    fn paths_follow_the_easing() {
+        let linear = path(&[(0, 0), (10, 0)], Easing::Linear, LoopMode::Loop);
+        assert_eq!([0, 2, 5, 10].map(|tick| linear.position_at(tick)), [(0, 0), (2, 0), (5, 0), (10, 0)]);
+        let ease = path(&[(0, 0), (10, 0)], Easing::EaseInOut, LoopMode::Loop);
+        assert_eq!([0, 2, 5, 8].map(|tick| ease.position_at(tick)), [(0, 0), (1, 0), (5, 0), (9, 0)]);
+        let sine = path(&[(0, 0), (10, 0)], Easing::Sine, LoopMode::Loop);
+        assert_eq!([0, 2, 5, 8].map(|tick| sine.position_at(tick)), [(0, 0), (1, 0), (5, 0), (9, 0)]);
+    }
+
+    #[test]
+    fn paths_loop() {
+        let looping = path(&[(0, 0), (10, 0), (10, 10)], Easing::Linear, LoopMode::Loop);
+        assert_eq!([5, 15, 25, 30, 35].map(|tick| looping.position_at(tick)), [(5, 0), (10, 5), (5, 5), (0, 0), (5, 0)]);
+    }
+
+    #[test]
+    fn paths_ping_pong() {
+        let ping_pong = path(&[(0, 0), (10, 0), (10, 10)], Easing::Linear, LoopMode::PingPong);
+        assert_eq!([15, 20, 25, 35, 40].map(|tick| ping_pong.position_at(tick)), [(10, 5), (10, 10), (10, 5), (5, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn paths_stop_at_the_end() {
+        let once = path(&[(0, 0), (10, 0), (10, 10)], Easing::Linear, LoopMode::Once);
+        assert_eq!([5, 15, 20, 25, 1000].map(|tick| once.position_at(tick)), [(5, 0), (10, 5), (10, 10), (10, 10), (10, 10)]);
+        let single = path(&[(3, 4)], Easing::Linear, LoopMode::Loop);
+        assert_eq!(single.position_at(7), (3, 4));
+    }
+
+    #[test]
+    fn paths_with_a_speed_handle_far_waypoints() {
+        let speed = |pos| Waypoint { pos, duration: None, speed: Some(2.) };
+        let near = Path { waypoints: vec![speed((0, 0)), speed((10, 0))], easing: Easing::Linear, mode: LoopMode::Once };
+        assert_eq!(near.duration(0, 1), 5);
+        let far = |pos| Waypoint { pos, duration: None, speed: Some(1e9) };
+        let far = Path {
+            waypoints: vec![far((-2_000_000_000, 0)), far((2_000_000_000, 0))], easing: Easing::Linear, mode: LoopMode::Once,
+        };
+        assert_eq!(far.duration(0, 1), 4);
+        assert_eq!(far.position_at(2), (0, 0));
+    }
+
+    #[test]
+    fn unknown_prefabs_are_errors() {
+        let mut map = Map::new((200, 100));
+        map.instances.push(Instance { prefab: "missing".to_string(), name: "m".to_string(), pos: (0, 0), repeat: None });
+        assert!(load("unknown-prefab", &map).is_err());
+    }
+}
@@ -1,6 +1,16 @@
+pub mod bot;
 pub mod components;
+pub mod diff;
+pub mod editor;
 pub mod entities;
 pub mod fonts;
+pub mod legacy;
+pub mod levels;
+pub mod lint;
 pub mod map;
+pub mod physics;
+pub mod render;
+pub mod save;
+pub mod snapshot;
 pub mod states;
 pub mod utils;
@@ -0,0 +1,1010 @@
+use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use amethyst::{
+    ecs::{ Entity, Join },
+    input::{ InputHandler, StringBindings, VirtualKeyCode },
+    prelude::*,
+    ui::{ Anchor, UiImage, UiTransform },
+};
+
+use crate::components;
+use crate::map;
+use crate::utils;
+
+// The keys that matter for one fixed update
+// the world only changes through step(), so replaying the same inputs gives the same result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Input {
+    pub left: bool,
+    pub right: bool,
+    pub jump: bool,
+    pub drop: bool,  // fall through one-way platforms
+}
+
+impl Input {
+    pub fn from_keys(input: &InputHandler<StringBindings>) -> Self {
+        Input {
+            left: input.key_is_down(VirtualKeyCode::A),
+            right: input.key_is_down(VirtualKeyCode::D),
+            jump: input.key_is_down(VirtualKeyCode::W),
+            drop: input.key_is_down(VirtualKeyCode::S),
+        }
+    }
+}
+
+// What the player touches after a step
+#[derive(Debug, Default)]
+pub struct Contacts {
+    pub dead: bool,
+    pub won: bool,
+    pub coins: Vec<String>,
+    pub keys: Vec<String>,
+    pub hits: Vec<(String, u32)>,  // monsters and hazards that deal damage
+    pub stomps: Vec<String>,  // monsters the player landed on
+    pub projectiles: Vec<String>,  // projectiles that hit the player
+}
+
+// helper function for finding the relative position between the player and other objects
+// the result helps resolve collisions
+pub fn get_rel_pos(world: &World) -> HashMap<String, Anchor> {
+    let uitrans_store = world.read_storage::<UiTransform>();
+    let player_store = world.read_storage::<components::PlayerComp>();
+    let obj_store = world.read_storage::<components::ObjectComp>();
+
+    let mut result = HashMap::new();
+
+    let mut player_uitrans: Option<&UiTransform> = None;
+    for (uitrans, _player) in (&uitrans_store, &player_store).join() {
+        player_uitrans = Some(uitrans);
+        break;
+    }
+
+    for (uitrans, obj) in (&uitrans_store, &obj_store).join() {
+        result.insert(obj.name.clone(),
+            utils::compare(player_uitrans.unwrap().clone(), uitrans.clone()));
+    }
+
+    result
+}
+
+// move a body out of a collidable it overlaps, to the side it came from
+// the direction is the side of the collidable the body was on before moving
+pub fn push_out(direction: (i32, i32), other: &UiTransform, body: &UiTransform, target: &mut (f32, f32)) {
+    if direction.1 != 0 {
+        target.1 = other.local_y
+            + 0.5 * (direction.1 - 1) as f32 * other.height
+            + 0.5 * (direction.1 + 1) as f32 * body.height;
+    }
+    else if direction.0 != 0 {
+        target.0 = other.local_x
+            + 0.5 * (direction.0 + 1) as f32 * other.width
+            + 0.5 * (direction.0 - 1) as f32 * body.width;
+    }
+}
+
+// whether any collidable covers the point (used to find ledges)
+fn is_solid(solids: &Vec<(UiTransform, bool)>, x: f32, y: f32) -> bool {
+    solids.iter().any(|(solid, _)| {
+        x >= solid.local_x && x <= solid.local_x + solid.width
+            && y <= solid.local_y && y >= solid.local_y - solid.height
+    })
+}
+
+// whether a body at the position (unscaled, top left) is inside the map and clear of solid collidables
+pub fn fits(world: &World, pos: (i32, i32), size: (u32, u32), map_size: (u32, u32)) -> bool {
+    let (x, y, w, h) = (pos.0 as f32, pos.1 as f32, size.0 as f32, size.1 as f32);
+    if x < 0. || y - h < 0. || x + w > map_size.0 as f32 || y > map_size.1 as f32 { return false; }
+    let dpi = utils::dpi();
+    let uitrans_store = world.read_storage::<UiTransform>();
+    let coll_store = world.read_storage::<components::CollidableComp>();
+    (&uitrans_store, &coll_store).join().all(|(solid, coll)| {
+        coll.one_way
+            || x * dpi >= solid.local_x + solid.width || (x + w) * dpi <= solid.local_x
+            || y * dpi <= solid.local_y - solid.height || (y - h) * dpi >= solid.local_y
+    })
+}
+
+// move the monsters with a behavior, with gravity and collisions
+pub fn move_enemies(world: &mut World) {
+    let mut uitrans_store = world.write_storage::<UiTransform>();
+    let player_store = world.read_storage::<components::PlayerComp>();
+    let collidable_store = world.read_storage::<components::CollidableComp>();
+    let mut enemy_store = world.write_storage::<components::EnemyComp>();
+
+    let player_x = (&uitrans_store, &player_store).join().next()
+        .map(|(uitrans, _)| uitrans.local_x + uitrans.width * 0.5);
+    let solids: Vec<(UiTransform, bool)> = (&uitrans_store, &collidable_store).join()
+        .map(|(uitrans, collidable)| (uitrans.clone(), collidable.one_way))
+        .collect();
+
+    for (uitrans, enemy) in (&mut uitrans_store, &mut enemy_store).join() {
+        let before = uitrans.clone();
+        let center_x = uitrans.local_x + uitrans.width * 0.5;
+        enemy.think(player_x.map(|x| ((x - center_x) / utils::dpi()) as i32));
+
+        // gravity and move
+        enemy.speed.1 -= utils::GRAVITY;
+        let x_before_scale = (uitrans.local_x / utils::dpi()) as i32;
+        let y_before_scale = (uitrans.local_y / utils::dpi()) as i32;
+        uitrans.local_x = (x_before_scale + enemy.speed.0) as f32 * utils::dpi();
+        uitrans.local_y = (y_before_scale + enemy.speed.1) as f32 * utils::dpi();
+
+        // resolve collisions like for the player
+        let mut target = (uitrans.local_x, uitrans.local_y);
+        let mut hit_wall = false;
+        enemy.on_ground = false;
+        for (solid, one_way) in solids.iter() {
+            if utils::compare(uitrans.clone(), solid.clone()) != Anchor::Middle { continue; }
+            let direction = utils::anchor_to_tuple(utils::compare(before.clone(), solid.clone()));
+            if *one_way && (direction.1 != 1 || enemy.speed.1 > 0) { continue; }
+            push_out(direction, solid, uitrans, &mut target);
+            if direction.1 != 0 {
+                enemy.speed.1 = 0;
+                enemy.on_ground = enemy.on_ground || direction.1 == 1;
+            }
+            else if direction.0 != 0 {
+                hit_wall = true;
+            }
+        }
+        uitrans.local_x = target.0;
+        uitrans.local_y = target.1;
+
+        // patrols turn around at walls and before walking off a ledge
+        if let map::Behavior::Patrol { .. } = enemy.behavior {
+            let front_x = if enemy.direction > 0 { uitrans.local_x + uitrans.width + utils::dpi() }
+                else { uitrans.local_x - utils::dpi() };
+            let below_y = uitrans.local_y - uitrans.height - utils::dpi();
+            if hit_wall || (enemy.on_ground && !is_solid(&solids, front_x, below_y)) {
+                enemy.direction = -enemy.direction;
+            }
+        }
+    }
+}
+
+// whether the player is in the line of fire within the distance, with nothing solid in between
+fn in_sight(from: (f32, f32), speed: (i32, i32), distance: u32, player: &UiTransform,
+    solids: &Vec<(UiTransform, bool)>) -> bool {
+    let target = (player.local_x + player.width * 0.5, player.local_y - player.height * 0.5);
+    let delta = (target.0 - from.0, target.1 - from.1);
+    let length = (delta.0 * delta.0 + delta.1 * delta.1).sqrt();
+    let norm = ((speed.0 * speed.0 + speed.1 * speed.1) as f32).sqrt();
+    if length > distance as f32 * utils::dpi() || norm == 0. { return false; }
+
+    // the player has to be ahead of the turret and cross its line of fire
+    let dir = (speed.0 as f32 / norm, speed.1 as f32 / norm);
+    let along = delta.0 * dir.0 + delta.1 * dir.1;
+    let across = (delta.0 * dir.1 - delta.1 * dir.0).abs();
+    if along <= 0. || across > player.width.max(player.height) * 0.5 { return false; }
+
+    let steps = (length / (4. * utils::dpi())) as i32;
+    (1..steps).all(|i| {
+        let t = i as f32 / steps as f32;
+        !is_solid(solids, from.0 + delta.0 * t, from.1 + delta.1 * t)
+    })
+}
+
+// fire the turrets that are due
+pub fn update_turrets(world: &mut World) {
+    let mut shots: Vec<(map::Projectile, String, String, (i32, i32))> = vec![];  // projectile, name, turret, position
+    {
+        let uitrans_store = world.read_storage::<UiTransform>();
+        let player_store = world.read_storage::<components::PlayerComp>();
+        let collidable_store = world.read_storage::<components::CollidableComp>();
+        let mut turret_store = world.write_storage::<components::TurretComp>();
+
+        let player = (&uitrans_store, &player_store).join().next().map(|(uitrans, _)| uitrans.clone());
+        let walls: Vec<(String, UiTransform)> = (&uitrans_store, &collidable_store).join()
+            .filter(|(_, collidable)| !collidable.one_way)
+            .map(|(uitrans, collidable)| (collidable.name.clone(), uitrans.clone()))
+            .collect();
+
+        for (uitrans, turret) in (&uitrans_store, &mut turret_store).join() {
+            if !turret.enabled { continue; }
+            turret.timer += 1;
+            if turret.timer < turret.interval { continue; }
+
+            // a turret with a sight range waits until it sees the player
+            let center = (uitrans.local_x + uitrans.width * 0.5, uitrans.local_y - uitrans.height * 0.5);
+            if let Some(sight) = turret.sight {
+                let solids = walls.iter()
+                    .filter(|(name, _)| *name != turret.name)
+                    .map(|(_, wall)| (wall.clone(), false))
+                    .collect();
+                let visible = player.as_ref()
+                    .map_or(false, |player| in_sight(center, turret.projectile.speed, sight, player, &solids));
+                if !visible { continue; }
+            }
+
+            turret.timer = 0;
+            turret.count += 1;
+            let size = turret.projectile.size;
+            let pos = (
+                (center.0 / utils::dpi()) as i32 - size.0 as i32 / 2,
+                (center.1 / utils::dpi()) as i32 + size.1 as i32 / 2);
+            shots.push((turret.projectile.clone(), format!("{}#{}", turret.name, turret.count), turret.name.clone(), pos));
+        }
+    }
+
+    if shots.is_empty() { return; }
+    let map_ent = match map::find_map_entity(world) {
+        Some(ent) => ent,
+        None => { return; }
+    };
+    for (projectile, name, turret, pos) in shots.into_iter() {
+        projectile.spawn(world, &map_ent, name, turret, pos, projectile.lifetime);
+    }
+}
+
+// move the projectiles and remove those that hit a wall or run out of time
+pub fn move_projectiles(world: &mut World) {
+    let mut expired: Vec<String> = vec![];
+    {
+        let mut uitrans_store = world.write_storage::<UiTransform>();
+        let collidable_store = world.read_storage::<components::CollidableComp>();
+        let mut projectile_store = world.write_storage::<components::ProjectileComp>();
+
+        let walls: Vec<(String, UiTransform)> = (&uitrans_store, &collidable_store).join()
+            .filter(|(_, collidable)| !collidable.one_way)
+            .map(|(uitrans, collidable)| (collidable.name.clone(), uitrans.clone()))
+            .collect();
+
+        for (uitrans, projectile) in (&mut uitrans_store, &mut projectile_store).join() {
+            let x_before_scale = (uitrans.local_x / utils::dpi()) as i32;
+            let y_before_scale = (uitrans.local_y / utils::dpi()) as i32;
+            uitrans.local_x = (x_before_scale + projectile.projectile.speed.0) as f32 * utils::dpi();
+            uitrans.local_y = (y_before_scale + projectile.projectile.speed.1) as f32 * utils::dpi();
+            projectile.lifetime = projectile.lifetime.saturating_sub(1);
+
+            let hit = walls.iter().any(|(name, wall)|
+                *name != projectile.turret && utils::compare(uitrans.clone(), wall.clone()) == Anchor::Middle);
+            if hit || projectile.lifetime == 0 { expired.push(projectile.name.clone()); }
+        }
+    }
+    remove_objs(world, expired);
+}
+
+// advance the world by one fixed update
+// returns the interactables touched by the player, as they were before their commands ran
+pub fn step(world: &mut World, input: &Input) -> Vec<components::InteractableComp> {
+    // get relative position
+    let rel_pos = get_rel_pos(world);
+    move_enemies(world);
+    update_turrets(world);
+    move_projectiles(world);
+
+    // access the storage
+    let mut uitrans_store = world.write_storage::<UiTransform>();
+    let mut player_store = world.write_storage::<components::PlayerComp>();
+    let obj_store = world.read_storage::<components::ObjectComp>();
+    let mut movable_store = world.write_storage::<components::MovableComp>();
+    let collidable_store = world.read_storage::<components::CollidableComp>();
+    let mut inter_store = world.write_storage::<components::InteractableComp>();
+    let lock_store = world.read_storage::<components::LockComp>();
+    let mut spring_store = world.write_storage::<components::SpringComp>();
+
+    // springs cooling down
+    let mut ready_springs: Vec<String> = vec![];
+    for spring in (&mut spring_store).join() {
+        if spring.timer == 0 { continue; }
+        spring.timer -= 1;
+        if spring.timer == 0 { ready_springs.push(spring.name.clone()); }
+    }
+
+    // find the player
+    let mut _player_comp: Option<&mut components::PlayerComp> = None;
+    for (_uitrans, player) in (&uitrans_store, &mut player_store).join() {
+        _player_comp = Some(player);
+        break;
+    }
+
+    // if the player is not found then there is some problem
+    if _player_comp.is_none() { println!("WARNING: PLAYER NOT FOUND!"); return vec![]; }
+    let player_comp = _player_comp.unwrap();
+
+    // update player status
+    if player_comp.invulnerable > 0 { player_comp.invulnerable -= 1; }
+    player_comp.momentum -= player_comp.momentum.signum();
+    player_comp.speed.0 = player_comp.momentum;
+    if input.left {
+        player_comp.speed.0 -= utils::PLAYER_SPEED;
+    }
+    if input.right {
+        player_comp.speed.0 += utils::PLAYER_SPEED;
+    }
+    if input.jump {
+        if player_comp.can_jump && player_comp.jump_count > 0 {
+            // an air jump never slows down a launch
+            player_comp.speed.1 = player_comp.speed.1.max(utils::PLAYER_JUMP);
+            player_comp.can_jump = false;
+            if !player_comp.on_ground { player_comp.jump_count -= 1; }
+        }
+    } else {
+        player_comp.can_jump = true;
+    }
+
+    // find movables and move them (and player)
+    let mut movables: Vec<&mut components::MovableComp> = Vec::new();
+    for (_uitrans, movable) in (&mut uitrans_store, &mut movable_store).join() {
+        movables.push(movable);
+    }
+    for movable in movables.iter_mut() { movable.move_(&obj_store, &mut uitrans_store); }
+    player_comp.move_(&obj_store, &mut uitrans_store);
+
+    // resolve collision (by changing the position of the player)
+    // will not deal with the case that the player is squeezed between two collidables
+    // the squeeze feature will be implemented in later versions
+    let mut _player_uitrans: Option<UiTransform> = None;
+    let mut target = (0., 0.);
+    for (uitrans, obj) in (&uitrans_store, &obj_store).join() {
+        if obj.name == player_comp.name {
+            _player_uitrans = Some(uitrans.clone());
+            target = (uitrans.local_x, uitrans.local_y);
+            break;
+        }
+    }
+    let player_uitrans = _player_uitrans.unwrap();
+    player_comp.on_ground = false;
+    let mut opened_doors: Vec<String> = vec![];
+    let mut launch: Option<(String, (i32, i32))> = None;
+    for (uitrans, collidable, lock, spring) in
+        (&uitrans_store, &collidable_store, lock_store.maybe(), (&mut spring_store).maybe()).join() {
+        if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {  // TODO: get player moving track
+            // a locked door opens (and uses up the key) instead of blocking the player
+            if let Some(lock) = lock {
+                if let Some(index) = player_comp.inventory.iter().position(|key| *key == lock.lock) {
+                    player_comp.inventory.remove(index);
+                    opened_doors.push(collidable.name.clone());
+                    continue;
+                }
+            }
+            let direction = utils::anchor_to_tuple(rel_pos[&collidable.name]);
+            // one-way platforms only catch a player falling onto them from above
+            if collidable.one_way && (direction.1 != 1 || player_comp.speed.1 > 0 || input.drop) { continue; }
+            push_out(direction, uitrans, &player_uitrans, &mut target);
+            if direction.1 != 0 {
+                player_comp.speed.1 = 0;
+                player_comp.on_ground = true;
+                player_comp.jump_count = 1;
+
+                // landing on a ready spring
+                if let Some(spring) = spring {
+                    if direction.1 == 1 && spring.timer == 0 {
+                        spring.timer = spring.cooldown;
+                        launch = Some((spring.name.clone(), spring.impulse));
+                    }
+                }
+            }
+            else if direction.0 != 0 {
+                player_comp.speed.0 = 0;
+                player_comp.momentum = 0;
+            }
+        }
+    }
+
+    // launch after all collisions so that landing on the ground next to the spring cannot cancel it
+    // the landing still resets the jumps, so one air jump is left after the launch
+    let mut launched_springs: Vec<String> = vec![];
+    if let Some((name, impulse)) = launch {
+        player_comp.speed.1 = impulse.1;
+        player_comp.momentum = impulse.0;
+        player_comp.on_ground = false;
+        launched_springs.push(name);
+    }
+
+    // the player flashes while invulnerable
+    let alpha = if (player_comp.invulnerable / 4) % 2 == 1 { 0.3 } else { 1. };
+    let mut img_store = world.write_storage::<UiImage>();
+    for (img, _player) in (&mut img_store, &player_store).join() {
+        if let UiImage::SolidColor(ref mut color) = img { color[3] = alpha; }
+    }
+    drop(img_store);
+
+    // update resolved player position
+    for (uitrans, _player) in (&mut uitrans_store, &player_store).join() {
+        uitrans.local_x = target.0;
+        uitrans.local_y = target.1;
+    }
+
+    // check if buttons are pressed
+    let mut pressed_buttons: Vec<components::InteractableComp> = vec![];
+    for (uitrans, inter) in (&uitrans_store, &mut inter_store).join() {
+        if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {
+            pressed_buttons.push(inter.clone());
+        }
+    }
+
+    drop(uitrans_store);
+    drop(player_store);
+    drop(movable_store);
+    drop(obj_store);
+    drop(collidable_store);
+    drop(inter_store);
+    drop(lock_store);
+    drop(spring_store);
+    remove_objs(world, opened_doors);
+    for name in launched_springs.iter() { trigger(world, name, "launch"); }
+    for name in ready_springs.iter() { trigger(world, name, "ready"); }
+
+    // execute commands
+    for inter in pressed_buttons.iter() {
+        let mut copy = inter.clone();
+        copy.exec(world);
+
+        // the commands run on a copy, so write the new state back
+        for stored in (&mut world.write_storage::<components::InteractableComp>()).join() {
+            if stored.name == copy.name { stored.state = copy.state.clone(); }
+        }
+    }
+
+    pressed_buttons
+}
+
+// run the commands of an interactable for the given state
+pub fn trigger(world: &mut World, name: &String, state: &str) {
+    let mut copy = match (&world.read_storage::<components::InteractableComp>()).join().find(|inter| inter.name == *name) {
+        Some(inter) => inter.clone(),
+        None => { return; }
+    };
+    copy.state = state.to_string();
+    copy.exec(world);
+    for stored in (&mut world.write_storage::<components::InteractableComp>()).join() {
+        if stored.name == copy.name { stored.state = copy.state.clone(); }
+    }
+}
+
+// check if the player touches an enemy, the target or coins, or is out of bounds
+pub fn check_contacts(world: &World) -> Contacts {
+    // access the storage
+    let uitrans_store = world.read_storage::<UiTransform>();
+    let player_store = world.read_storage::<components::PlayerComp>();
+    let obj_store = world.read_storage::<components::ObjectComp>();
+    let inter_store = world.read_storage::<components::InteractableComp>();
+    let damage_store = world.read_storage::<components::DamageComp>();
+
+    let mut contacts = Contacts::default();
+
+    // find the player
+    let mut _player_uitrans: Option<&UiTransform> = None;
+    for (uitrans, _player) in (&uitrans_store, &player_store).join() {
+        _player_uitrans = Some(uitrans);
+        break;
+    }
+    let player_uitrans = match _player_uitrans {
+        Some(uitrans) => uitrans,
+        None => { return contacts; }
+    };
+
+    // check if player is out of bounds
+    contacts.dead = player_uitrans.local_y < utils::LOWER_BOUND as f32 * utils::dpi();
+
+    // where the player was before the last move, to tell a stomp from a side contact
+    let mut last_uitrans = player_uitrans.clone();
+    if let Some(pos) = (&player_store).join().next().and_then(|player| player.last_pos) {
+        last_uitrans.local_x = pos.0 as f32 * utils::dpi();
+        last_uitrans.local_y = pos.1 as f32 * utils::dpi();
+    }
+
+    // check if collide with enemy, target, or coin
+    for (uitrans, _inter, obj, damage) in (&uitrans_store, &inter_store, &obj_store, damage_store.maybe()).join() {
+        if utils::compare(player_uitrans.clone(), uitrans.clone()) != Anchor::Middle { continue; }
+        match obj.type_ {
+            components::ObjectType::Monster | components::ObjectType::Hazard | components::ObjectType::Projectile => {
+                if let components::ObjectType::Projectile = obj.type_ { contacts.projectiles.push(obj.name.clone()); }
+                let from_above = utils::anchor_to_tuple(utils::compare(last_uitrans.clone(), uitrans.clone())).1 == 1;
+                if from_above && damage.map_or(false, |damage| damage.stompable) {
+                    contacts.stomps.push(obj.name.clone());
+                    continue;
+                }
+                match damage.and_then(|damage| damage.damage) {
+                    Some(damage) => { contacts.hits.push((obj.name.clone(), damage)); }
+                    None => { contacts.dead = true; }
+                }
+            }
+            components::ObjectType::Target => { contacts.won = true; }
+            components::ObjectType::Coin => { contacts.coins.push(obj.name.clone()); }
+            components::ObjectType::Key => { contacts.keys.push(obj.name.clone()); }
+            _ => {}
+        }
+    }
+
+    contacts
+}
+
+// apply the strongest hit to the player, returns true if the player runs out of health
+// the player is knocked away from the source and cannot be hurt again for a while
+pub fn hurt(world: &mut World, hits: Vec<(String, u32)>) -> bool {
+    let (source, damage) = match hits.into_iter().max_by_key(|hit| hit.1) {
+        Some(hit) => hit,
+        None => { return false; }
+    };
+    let uitrans_store = world.read_storage::<UiTransform>();
+    let obj_store = world.read_storage::<components::ObjectComp>();
+    let mut player_store = world.write_storage::<components::PlayerComp>();
+    let source_x = (&uitrans_store, &obj_store).join()
+        .find(|(_, obj)| obj.name == source)
+        .map(|(uitrans, _)| uitrans.local_x + uitrans.width * 0.5);
+
+    if let Some((uitrans, player)) = (&uitrans_store, &mut player_store).join().next() {
+        if player.invulnerable > 0 { return false; }
+        player.health = player.health.saturating_sub(damage);
+        player.invulnerable = utils::INVULNERABLE_TICKS;
+        let away = match source_x {
+            Some(x) if x > uitrans.local_x + uitrans.width * 0.5 => -1,
+            _ => 1,
+        };
+        player.momentum = away * utils::KNOCKBACK.0;
+        player.speed.1 = utils::KNOCKBACK.1;
+        return player.health == 0;
+    }
+    false
+}
+
+// remove the stomped monsters and bounce the player off them
+pub fn stomp(world: &mut World, names: Vec<String>) {
+    if names.is_empty() { return; }
+    for player in (&mut world.write_storage::<components::PlayerComp>()).join() {
+        player.speed.1 = utils::STOMP_BOUNCE;
+        player.jump_count = 1;
+    }
+    remove_objs(world, names);
+}
+
+// move the player to the position, standing still with full health and with the keys collected so far
+pub fn respawn(world: &mut World, pos: (i32, i32)) {
+    let mut uitrans_store = world.write_storage::<UiTransform>();
+    let mut player_store = world.write_storage::<components::PlayerComp>();
+    for (uitrans, player) in (&mut uitrans_store, &mut player_store).join() {
+        uitrans.local_x = pos.0 as f32 * utils::dpi();
+        uitrans.local_y = pos.1 as f32 * utils::dpi();
+        let inventory = player.inventory.clone();
+        *player = components::PlayerComp::new(player.name.clone(), player.max_health);
+        player.inventory = inventory;
+    }
+}
+
+// the health of the player as (current, max)
+pub fn health(world: &World) -> (u32, u32) {
+    (&world.read_storage::<components::PlayerComp>()).join().next()
+        .map_or((0, 0), |player| (player.health, player.max_health))
+}
+
+// put the keys with the names into the inventory of the player
+pub fn collect_keys(world: &mut World, names: Vec<String>) {
+    if names.is_empty() { return; }
+    let locks: Vec<String> = (&world.read_storage::<components::LockComp>()).join()
+        .filter(|lock| names.contains(&lock.name))
+        .map(|lock| lock.lock.clone())
+        .collect();
+    for player in (&mut world.write_storage::<components::PlayerComp>()).join() {
+        player.inventory.extend(locks.iter().cloned());
+    }
+    remove_objs(world, names);
+}
+
+// the keys currently held by the player
+pub fn inventory(world: &World) -> Vec<String> {
+    (&world.read_storage::<components::PlayerComp>()).join().next()
+        .map_or(vec![], |player| player.inventory.clone())
+}
+
+// delete an object with the name
+pub fn remove_objs(world: &mut World, names: Vec<String>) {
+    let obj_store = world.read_storage::<components::ObjectComp>();
+    let mut entities_to_be_removed: Vec<Entity> = vec![];
+    for entity in (world.entities()).join() {
+        match obj_store.get(entity) {
+            Some(obj) => {
+                if names.contains(&obj.name) {
+                    entities_to_be_removed.push(entity.clone());
+                }
+            }
+            None => {}
+        }
+    }
+
+    drop(obj_store);
+    for ent in entities_to_be_removed.iter() {
+        world.delete_entity(*ent).expect("Entity does not exist");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::{ Outcome, Sim };
+
+    const RIGHT: Input = Input { left: false, right: true, jump: false, drop: false };
+    const STILL: Input = Input { left: false, right: false, jump: false, drop: false };
+    const JUMP: Input = Input { left: false, right: false, jump: true, drop: false };
+    const DROP: Input = Input { left: false, right: false, jump: false, drop: true };
+
+    // an empty level with a floor the player lands on, standing at (50, 70)
+    fn level() -> map::Map {
+        let mut map = map::Map::new((1000, 500));
+        map.obstacles.push(map::Obstacle {
+            name: "floor".to_string(), pos: (0, 20), size: (1000, 20), color: (0, 0, 0), one_way: false,
+        });
+        map
+    }
+
+    // hold the input, stopping early if the run ends, returns the outcome and the collected coins
+    fn hold(sim: &mut Sim, input: Input, ticks: u32) -> (Outcome, Vec<String>) {
+        let mut coins = vec![];
+        for _ in 0..ticks {
+            let (outcome, collected) = sim.step(&input);
+            coins.extend(collected);
+            if outcome != Outcome::Playing { return (outcome, coins); }
+        }
+        (Outcome::Playing, coins)
+    }
+
+    fn player(sim: &Sim) -> components::PlayerComp {
+        sim.save().player.unwrap().comp
+    }
+
+    fn player_pos(sim: &Sim) -> (i32, i32) {
+        sim.save().player.unwrap().pos
+    }
+
+    #[test]
+    fn respawning_restores_the_checkpoint_and_keeps_the_keys() {
+        let mut map = level();
+        map.coins.push(map::Coin { name: "c".to_string(), pos: (200, 60), size: (10, 10), color: (255, 255, 0) });
+        map.keys.push(map::Key {
+            name: "k".to_string(), id: "red".to_string(), pos: (120, 60), size: (10, 10), color: (255, 0, 0),
+        });
+        let mut sim = Sim::new(map);
+
+        // the key is collected before the checkpoint, the coin after it
+        hold(&mut sim, RIGHT, 12);
+        assert_eq!(inventory(&sim.world), vec!["red".to_string()]);
+        let checkpoint = sim.save();
+        let checkpoint_pos = player_pos(&sim);
+        assert_eq!(hold(&mut sim, RIGHT, 13), (Outcome::Playing, vec!["c".to_string()]));
+        for player in (&mut sim.world.write_storage::<components::PlayerComp>()).join() { player.health = 1; }
+
+        sim.load(&checkpoint);
+        respawn(&mut sim.world, checkpoint_pos);
+        assert_eq!(player_pos(&sim), checkpoint_pos);
+        assert_eq!(player(&sim).speed, (0, 0));
+        assert_eq!(health(&sim.world), (utils::PLAYER_HEALTH, utils::PLAYER_HEALTH));
+        assert_eq!(inventory(&sim.world), vec!["red".to_string()]);
+        assert_eq!(sim.save().removed, vec!["k".to_string()]);
+        assert_eq!(hold(&mut sim, RIGHT, 13).1, vec!["c".to_string()]);
+    }
+
+    #[test]
+    fn rewinding_goes_back_in_position_coins_and_time() {
+        let mut map = level();
+        map.coins.push(map::Coin { name: "c".to_string(), pos: (120, 60), size: (10, 10), color: (255, 255, 0) });
+        let mut sim = Sim::new(map);
+        let mut history = crate::snapshot::History::new(60);
+        let mut positions = vec![];
+        for ticks in 0..20 {
+            let mut snapshot = sim.save();
+            snapshot.ticks = ticks;
+            positions.push(snapshot.player.as_ref().unwrap().pos);
+            history.record(snapshot);
+            sim.step(&RIGHT);
+        }
+        assert_eq!(sim.save().removed, vec!["c".to_string()]);
+
+        // rewinding restores the tick count, so the timer does not keep running while rewinding
+        let mut timer = crate::entities::Timer::default();
+        timer.ticks = 19;
+        timer.splits.push(crate::save::Split { name: "cp".to_string(), ticks: 15 });
+        for ticks in (0..19).rev() {
+            let snapshot = history.rewind().unwrap().clone();
+            assert_eq!(snapshot.ticks, ticks);
+            sim.load(&snapshot);
+            timer.rewind_to(&mut sim.world, snapshot.ticks);
+            assert_eq!(player_pos(&sim), positions[ticks as usize]);
+        }
+        assert!(history.rewind().is_none());
+        assert_eq!(timer.ticks, 0);
+        assert!(timer.splits.is_empty());
+        assert!(sim.save().removed.is_empty());
+    }
+
+    // a door across the floor at x 200 and a key on the way to it
+    fn locked_level(key_color: (u32, u32, u32)) -> map::Map {
+        let mut map = level();
+        map.keys.push(map::Key { name: "k".to_string(), id: "".to_string(), pos: (120, 60), size: (10, 10), color: key_color });
+        map.doors.push(map::Door { name: "d".to_string(), id: "".to_string(), pos: (200, 120), size: (20, 100), color: (255, 0, 0) });
+        map
+    }
+
+    #[test]
+    fn matching_keys_open_doors() {
+        let mut sim = Sim::new(locked_level((255, 0, 0)));
+        hold(&mut sim, RIGHT, 12);
+        assert_eq!(inventory(&sim.world), vec!["#ff0000".to_string()]);
+        hold(&mut sim, RIGHT, 20);
+        assert!(player_pos(&sim).0 > 220);
+        assert!(inventory(&sim.world).is_empty());
+        assert_eq!(sim.save().removed, vec!["k".to_string(), "d".to_string()]);
+    }
+
+    #[test]
+    fn doors_block_players_without_a_matching_key() {
+        let mut sim = Sim::new(locked_level((0, 0, 255)));
+        hold(&mut sim, RIGHT, 32);
+        assert_eq!(player_pos(&sim).0, 170);
+        assert_eq!(inventory(&sim.world), vec!["#0000ff".to_string()]);
+        assert_eq!(sim.save().removed, vec!["k".to_string()]);
+    }
+
+    // a one-way platform at y 150 above the floor
+    fn platform_level() -> map::Map {
+        let mut map = level();
+        map.obstacles.push(map::Obstacle {
+            name: "p".to_string(), pos: (0, 150), size: (1000, 10), color: (0, 0, 0), one_way: true,
+        });
+        map
+    }
+
+    #[test]
+    fn one_way_platforms_catch_falling_players_and_let_them_drop() {
+        let mut map = platform_level();
+        map.player.pos = (50, 300);
+        let mut sim = Sim::new(map);
+        hold(&mut sim, STILL, 30);
+        assert_eq!(player_pos(&sim), (50, 200));
+        assert!(player(&sim).on_ground);
+
+        hold(&mut sim, DROP, 2);
+        hold(&mut sim, STILL, 30);
+        assert_eq!(player_pos(&sim), (50, 70));
+    }
+
+    #[test]
+    fn one_way_platforms_can_be_jumped_through_from_below() {
+        let mut sim = Sim::new(platform_level());
+        hold(&mut sim, STILL, 10);
+        assert_eq!(player_pos(&sim), (50, 70));
+        hold(&mut sim, JUMP, 1);
+        hold(&mut sim, STILL, 60);
+        assert_eq!(player_pos(&sim), (50, 200));
+    }
+
+    #[test]
+    fn springs_launch_once_per_cooldown() {
+        let mut map = level();
+        map.springs.push(map::Spring {
+            name: "s".to_string(), pos: (40, 30), size: (50, 10), color: (0, 0, 255), impulse: (0, 20),
+            cooldown: 100, active_color: Some((255, 0, 0)),
+        });
+        let mut sim = Sim::new(map);
+
+        // the player keeps landing on the spring, but it only launches when it is ready
+        let mut launches = vec![];
+        for tick in 0..160 {
+            sim.step(&STILL);
+            if player(&sim).speed.1 == 20 { launches.push(tick); }
+            if launches.len() == 1 && tick == launches[0] + 50 {
+                let snapshot = sim.save();
+                assert_eq!(snapshot.states["s"], "cooldown");
+                assert_eq!(snapshot.colors["s"], utils::get_color([255, 0, 0, 255]));
+                assert_eq!(snapshot.timers["s"], 50);
+                assert_eq!(player_pos(&sim), (50, 80));
+            }
+        }
+        assert_eq!(launches.len(), 2);
+        assert_eq!(launches[1] - launches[0], 100);
+    }
+
+    #[test]
+    fn springs_change_back_when_ready() {
+        let mut map = level();
+        map.player.pos = (300, 100);
+        map.springs.push(map::Spring {
+            name: "s".to_string(), pos: (40, 30), size: (50, 10), color: (0, 0, 255), impulse: (0, 20),
+            cooldown: 20, active_color: Some((255, 0, 0)),
+        });
+        let mut sim = Sim::new(map);
+        trigger(&mut sim.world, &"s".to_string(), "launch");
+        for spring in (&mut sim.world.write_storage::<components::SpringComp>()).join() { spring.timer = 20; }
+        hold(&mut sim, STILL, 20);
+        let snapshot = sim.save();
+        assert_eq!(snapshot.states["s"], "close");
+        assert_eq!(snapshot.colors["s"], utils::get_color([0, 0, 255, 255]));
+        assert_eq!(snapshot.timers["s"], 0);
+    }
+
+    fn hazard_level(damage: Option<u32>) -> map::Map {
+        let mut map = level();
+        map.hazards.push(map::Hazard { name: "h".to_string(), pos: (150, 40), size: (20, 20), color: (255, 0, 0), damage });
+        map
+    }
+
+    #[test]
+    fn hazards_hurt_and_knock_back_the_player() {
+        let mut sim = Sim::new(hazard_level(Some(1)));
+        let mut ticks = 0;
+        while health(&sim.world).0 == utils::PLAYER_HEALTH && ticks < 30 {
+            assert_eq!(sim.step(&RIGHT).0, Outcome::Playing);
+            ticks += 1;
+        }
+        let hurt = player(&sim);
+        assert_eq!(hurt.health, utils::PLAYER_HEALTH - 1);
+        assert_eq!(hurt.invulnerable, utils::INVULNERABLE_TICKS);
+        assert_eq!(hurt.momentum, -utils::KNOCKBACK.0);
+        assert_eq!(hurt.speed.1, utils::KNOCKBACK.1);
+
+        // walking back into the hazard does not hurt while invulnerable
+        let x = player_pos(&sim).0;
+        assert_eq!(hold(&mut sim, RIGHT, 2).0, Outcome::Playing);
+        assert!(player_pos(&sim).0 < x);
+        assert_eq!(hold(&mut sim, RIGHT, 30).0, Outcome::Playing);
+        assert_eq!(health(&sim.world).0, utils::PLAYER_HEALTH - 1);
+    }
+
+    #[test]
+    fn running_out_of_health_is_fatal() {
+        let mut sim = Sim::new(hazard_level(Some(utils::PLAYER_HEALTH)));
+        assert_eq!(hold(&mut sim, RIGHT, 30).0, Outcome::Dead);
+        let mut sim = Sim::new(hazard_level(None));
+        assert_eq!(hold(&mut sim, RIGHT, 30).0, Outcome::Dead);
+    }
+
+    fn monster(name: &str, pos: (i32, i32), behavior: map::Behavior) -> map::Monster {
+        map::Monster {
+            name: name.to_string(), track: vec![map::Track { pos, speed: (0, 0) }], path: None, size: (20, 20),
+            color: (128, 0, 0), damage: None, stompable: false, behavior: Some(behavior),
+        }
+    }
+
+    fn enemy_pos(sim: &Sim, name: &str) -> (i32, i32) {
+        sim.save().enemies[name].pos
+    }
+
+    #[test]
+    fn patrols_turn_around_at_ledges() {
+        let mut map = level();
+        map.obstacles.push(map::Obstacle {
+            name: "ledge".to_string(), pos: (400, 120), size: (100, 20), color: (0, 0, 0), one_way: false,
+        });
+        map.monsters.push(monster("m", (450, 140), map::Behavior::Patrol { speed: 2 }));
+        let mut sim = Sim::new(map);
+        let mut xs = vec![];
+        for _ in 0..200 {
+            assert_eq!(sim.step(&STILL).0, Outcome::Playing);
+            let pos = enemy_pos(&sim, "m");
+            assert_eq!(pos.1, 140);
+            xs.push(pos.0);
+        }
+        assert!(xs.iter().all(|x| *x >= 398 && *x + 20 <= 502));
+        assert!(*xs.iter().min().unwrap() < 410 && *xs.iter().max().unwrap() > 470);
+    }
+
+    #[test]
+    fn chasers_only_follow_players_within_their_radius() {
+        let mut map = level();
+        map.monsters.push(monster("m", (300, 40), map::Behavior::Chase { speed: 2, radius: 200 }));
+        let mut sim = Sim::new(map.clone());
+        assert_eq!(hold(&mut sim, STILL, 60).0, Outcome::Playing);
+        assert_eq!(enemy_pos(&sim, "m"), (300, 40));
+
+        map.monsters[0].behavior = Some(map::Behavior::Chase { speed: 2, radius: 300 });
+        let mut sim = Sim::new(map);
+        hold(&mut sim, STILL, 10);
+        assert_eq!(enemy_pos(&sim, "m"), (280, 40));
+        assert_eq!(hold(&mut sim, STILL, 200).0, Outcome::Dead);
+    }
+
+    #[test]
+    fn jumpers_leap_toward_the_player() {
+        let mut map = level();
+        map.monsters.push(monster("m", (300, 40), map::Behavior::Jump { speed: 3, jump: 20, radius: 300 }));
+        let mut sim = Sim::new(map);
+        // the first tick lands it on the floor, it only jumps from the ground
+        hold(&mut sim, STILL, 2);
+        let enemy = sim.save().enemies["m"].clone();
+        assert_eq!(enemy.comp.speed, (-3, 18));
+        assert_eq!(enemy.pos, (297, 58));
+        assert_eq!(enemy.comp.direction, -1);
+
+        // the direction is kept in the air and the jump ends on the floor
+        hold(&mut sim, STILL, 19);
+        let enemy = sim.save().enemies["m"].clone();
+        assert!(enemy.comp.on_ground);
+        assert_eq!(enemy.pos, (240, 40));
+    }
+
+    fn stompable_level(player_pos: (i32, i32), monster_pos: (i32, i32), stompable: bool) -> map::Map {
+        let mut map = level();
+        map.player.pos = player_pos;
+        map.monsters.push(map::Monster {
+            name: "m".to_string(), track: vec![map::Track { pos: monster_pos, speed: (0, 0) }], path: None,
+            size: (30, 20), color: (128, 0, 0), damage: None, stompable, behavior: None,
+        });
+        map
+    }
+
+    #[test]
+    fn landing_on_stompable_monsters_defeats_them() {
+        let mut sim = Sim::new(stompable_level((50, 200), (50, 40), true));
+        let mut ticks = 0;
+        while sim.save().removed.is_empty() && ticks < 30 {
+            assert_eq!(sim.step(&STILL).0, Outcome::Playing);
+            ticks += 1;
+        }
+        assert_eq!(sim.save().removed, vec!["m".to_string()]);
+        assert_eq!(player(&sim).speed.1, utils::STOMP_BOUNCE);
+        assert_eq!(player(&sim).jump_count, 1);
+    }
+
+    #[test]
+    fn stompable_monsters_still_kill_from_the_side() {
+        let mut sim = Sim::new(stompable_level((50, 100), (200, 40), true));
+        assert_eq!(hold(&mut sim, RIGHT, 30).0, Outcome::Dead);
+    }
+
+    #[test]
+    fn other_monsters_kill_from_above() {
+        let mut sim = Sim::new(stompable_level((50, 200), (50, 40), false));
+        assert_eq!(hold(&mut sim, STILL, 30).0, Outcome::Dead);
+    }
+
+    // a turret on the floor at x 300 firing to the left, at the height of the player
+    fn turret_level(sight: Option<u32>, enabled: bool) -> map::Map {
+        let mut map = level();
+        map.turrets.push(map::Turret {
+            name: "t".to_string(), pos: (300, 40), size: (20, 20), color: (64, 64, 64), interval: 30, sight, enabled,
+            projectile: map::Projectile { size: (6, 6), color: (0, 0, 0), speed: (-4, 0), lifetime: 200, damage: Some(1) },
+        });
+        map
+    }
+
+    #[test]
+    fn turrets_fire_projectiles_that_hurt_the_player() {
+        let mut sim = Sim::new(turret_level(None, true));
+        hold(&mut sim, STILL, 29);
+        assert!(sim.save().projectiles.is_empty());
+        hold(&mut sim, STILL, 1);
+        let projectiles = sim.save().projectiles;
+        assert_eq!(projectiles.keys().collect::<Vec<_>>(), vec!["t#1"]);
+        assert_eq!(projectiles["t#1"].pos, (303, 33));
+
+        // the projectile is used up when it hits the player
+        let mut ticks = 0;
+        while health(&sim.world).0 == utils::PLAYER_HEALTH && ticks < 60 {
+            assert_eq!(sim.step(&STILL).0, Outcome::Playing);
+            ticks += 1;
+        }
+        assert_eq!(health(&sim.world).0, utils::PLAYER_HEALTH - 1);
+        assert!(!sim.save().projectiles.contains_key("t#1"));
+        assert_eq!(sim.save().turrets["t"].count, 2);
+    }
+
+    #[test]
+    fn disabled_turrets_are_enabled_by_commands() {
+        let mut map = turret_level(None, false);
+        let mut command = HashMap::new();
+        command.insert("close".to_string(), vec![
+            vec!["object".to_string(), "t".to_string(), "enabled".to_string(), "True".to_string()],
+            vec!["state".to_string(), "open".to_string()],
+        ]);
+        map.switches.push(map::Switch { name: "s".to_string(), pos: (150, 70), size: (10, 50), color: (0, 0, 0), command });
+        let mut sim = Sim::new(map);
+        hold(&mut sim, STILL, 60);
+        assert_eq!(sim.save().turrets["t"].count, 0);
+
+        hold(&mut sim, RIGHT, 15);
+        assert!(sim.save().turrets["t"].enabled);
+        hold(&mut sim, STILL, 30);
+        assert_eq!(sim.save().turrets["t"].count, 1);
+    }
+
+    #[test]
+    fn turrets_with_a_sight_wait_for_the_player() {
+        let mut sim = Sim::new(turret_level(Some(100), true));
+        hold(&mut sim, STILL, 60);
+        assert_eq!(sim.save().turrets["t"].count, 0);
+
+        hold(&mut sim, RIGHT, 19);
+        hold(&mut sim, STILL, 1);
+        assert_eq!(sim.save().turrets["t"].count, 1);
+    }
+}
+
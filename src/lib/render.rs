@@ -0,0 +1,429 @@
+use crate::components;
+use crate::map;
+use crate::utils;
+
+// Headless rendering of a level to PNG or SVG, without a window or a GPU
+// the map is turned into a list of shapes first, which both formats draw the same way
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Rect { pos: (i32, i32), size: (u32, u32), color: (u32, u32, u32) },  // top left corner, y up like the map
+    Text { pos: (i32, i32), size: (u32, u32), text: String, color: (u32, u32, u32) },  // centered in its box
+    Track { points: Vec<(i32, i32)>, closed: bool },
+    Arrow { from: (i32, i32), to: (i32, i32) },
+}
+
+const TRACK_COLOR: (u32, u32, u32) = (255, 255, 255);
+const ARROW_COLOR: (u32, u32, u32) = (0, 0, 192);
+
+fn center(pos: (i32, i32), size: (u32, u32)) -> (i32, i32) {
+    (pos.0 + size.0 as i32 / 2, pos.1 - size.1 as i32 / 2)
+}
+
+// the shapes of a level, from back to front
+pub fn shapes(map: &map::Map) -> Vec<Shape> {
+    let mut shapes = vec![];
+    let rect = |pos: (i32, i32), size: (u32, u32), color: (u32, u32, u32)| Shape::Rect { pos, size, color };
+
+    // same order as the z values of the objects in the game
+    shapes.extend(map.descriptions.iter().map(|obj| Shape::Text {
+        pos: obj.pos, size: (obj.size.0.max(0) as u32, obj.size.1.max(0) as u32),
+        text: obj.text.clone(), color: obj.color,
+    }));
+    shapes.extend(map.obstacles.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.doors.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.springs.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.targets.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.checkpoints.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.elevators.iter().map(|obj| rect(map::start_pos(&obj.track, &obj.path), obj.size, obj.color)));
+    shapes.extend(map.turrets.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.switches.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.hazards.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.monsters.iter().map(|obj| rect(map::start_pos(&obj.track, &obj.path), obj.size, obj.color)));
+    shapes.extend(map.coins.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.extend(map.keys.iter().map(|obj| rect(obj.pos, obj.size, obj.color)));
+    shapes.push(rect(map.player.pos, map.player.size, map.player.color));
+
+    // tracks through the centers of the waypoints, monsters with a behavior do not follow theirs
+    let mut tracks: Vec<(&Vec<map::Track>, &Option<map::Path>, (u32, u32))> = vec![];
+    tracks.extend(map.monsters.iter().filter(|obj| obj.behavior.is_none()).map(|obj| (&obj.track, &obj.path, obj.size)));
+    tracks.extend(map.elevators.iter().map(|obj| (&obj.track, &obj.path, obj.size)));
+    for (track, path, size) in tracks {
+        let (points, closed): (Vec<(i32, i32)>, bool) = match path {
+            Some(path) => (
+                path.waypoints.iter().map(|wp| center(wp.pos, size)).collect(),
+                path.mode == map::LoopMode::Loop),
+            None => (track.iter().map(|tr| center(tr.pos, size)).collect(), true),
+        };
+        if points.len() > 1 {
+            shapes.push(Shape::Track { points, closed });
+        }
+    }
+
+    // arrows from each switch to the objects its commands change
+    let objects = map.objects();
+    for switch in map.switches.iter() {
+        let mut targets: Vec<String> = switch.command.values().flatten()
+            .flat_map(|command| components::Command::parse(command).map_or(vec![], |command| command.targets()))
+            .collect();
+        targets.sort();
+        targets.dedup();
+        for target in targets.iter() {
+            let to = if target == "player" {
+                Some(center(map.player.pos, map.player.size))
+            } else {
+                objects.iter().find(|obj| obj.name() == target).map(|obj| center(obj.pos(), obj.style().0))
+            };
+            if let Some(to) = to {
+                shapes.push(Shape::Arrow { from: center(switch.pos, switch.size), to });
+            }
+        }
+    }
+    shapes
+}
+
+// the two ends of the head of an arrow, in image coordinates
+fn arrow_head(from: (f32, f32), to: (f32, f32), length: f32) -> [(f32, f32); 2] {
+    let angle = (to.1 - from.1).atan2(to.0 - from.0);
+    let spread = std::f32::consts::PI / 7.;
+    [
+        (to.0 - length * (angle - spread).cos(), to.1 - length * (angle - spread).sin()),
+        (to.0 - length * (angle + spread).cos(), to.1 - length * (angle + spread).sin()),
+    ]
+}
+
+// SVG with one unit per unscaled pixel of the map
+pub fn to_svg(map: &map::Map) -> String {
+    let (width, height) = map.size;
+    let flip = |p: (i32, i32)| (p.0, height as i32 - p.1);
+    let rgb = |color: (u32, u32, u32)| format!("rgb({},{},{})", color.0, color.1, color.2);
+    let background = utils::BACKGROUND_COLOR;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n",
+        width, height, width, height);
+    svg += &format!("  <rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+        width, height, rgb((background[0], background[1], background[2])));
+    for shape in shapes(map) {
+        svg += &match shape {
+            Shape::Rect { pos, size, color } => {
+                let (x, y) = flip(pos);
+                format!("  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+                    x, y, size.0, size.1, rgb(color))
+            }
+            Shape::Text { pos, size, text, color } => {
+                let (x, y) = flip(pos);
+                let text = text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+                format!(concat!(
+                    "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-dasharray=\"4\"/>\n",
+                    "  <text x=\"{}\" y=\"{}\" fill=\"{}\" font-size=\"{}\" text-anchor=\"middle\" ",
+                    "dominant-baseline=\"middle\">{}</text>\n"),
+                    x - size.0 as i32 / 2, y - size.1 as i32 / 2, size.0, size.1, rgb(color),
+                    x, y, rgb(color), (size.1 / 2).max(8), text)
+            }
+            Shape::Track { points, closed } => {
+                let points: Vec<String> = points.into_iter().map(flip).map(|(x, y)| format!("{},{}", x, y)).collect();
+                format!("  <{} points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\" stroke-dasharray=\"6 4\"/>\n",
+                    if closed { "polygon" } else { "polyline" }, points.join(" "), rgb(TRACK_COLOR))
+            }
+            Shape::Arrow { from, to } => {
+                let (from, to) = (flip(from), flip(to));
+                let head = arrow_head((from.0 as f32, from.1 as f32), (to.0 as f32, to.1 as f32), 12.);
+                format!(concat!(
+                    "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+                    "  <polygon points=\"{},{} {:.1},{:.1} {:.1},{:.1}\" fill=\"{}\"/>\n"),
+                    from.0, from.1, to.0, to.1, rgb(ARROW_COLOR),
+                    to.0, to.1, head[0].0, head[0].1, head[1].0, head[1].1, rgb(ARROW_COLOR))
+            }
+        };
+    }
+    svg + "</svg>\n"
+}
+
+// An RGBA image
+#[derive(Clone)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,  // row by row from the top, 4 bytes per pixel
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, color: (u32, u32, u32)) -> Self {
+        let pixel = [color.0 as u8, color.1 as u8, color.2 as u8, 255];
+        Canvas { width, height, pixels: pixel.repeat((width * height) as usize) }
+    }
+
+    fn set(&mut self, x: i32, y: i32, color: (u32, u32, u32)) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 { return; }
+        let index = ((y as u32 * self.width + x as u32) * 4) as usize;
+        self.pixels[index..index + 3].copy_from_slice(&[color.0 as u8, color.1 as u8, color.2 as u8]);
+    }
+
+    fn fill(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, color: (u32, u32, u32)) {
+        // at least one pixel so that small objects stay visible in thumbnails
+        let (x0, y0) = (x0.round() as i32, y0.round() as i32);
+        let (x1, y1) = ((x1.round() as i32).max(x0 + 1), (y1.round() as i32).max(y0 + 1));
+        for y in y0.max(0)..y1.min(self.height as i32) {
+            for x in x0.max(0)..x1.min(self.width as i32) {
+                self.set(x, y, color);
+            }
+        }
+    }
+
+    fn line(&mut self, from: (f32, f32), to: (f32, f32), color: (u32, u32, u32), dashed: bool) {
+        let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.) as i32;
+        for i in 0..=steps {
+            if dashed && i % 10 >= 6 { continue; }
+            let t = i as f32 / steps as f32;
+            let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+            self.set(x.round() as i32, y.round() as i32, color);
+        }
+    }
+
+    // blend every pixel with a color, e.g. to fade a thumbnail behind text
+    pub fn fade(&mut self, color: (u32, u32, u32), amount: f32) {
+        let color = [color.0 as f32, color.1 as f32, color.2 as f32];
+        for pixel in self.pixels.chunks_mut(4) {
+            for c in 0..3 {
+                pixel[c] = (pixel[c] as f32 * (1. - amount) + color[c] * amount).round() as u8;
+            }
+        }
+    }
+
+    pub fn to_png(&self) -> Vec<u8> {
+        encode_png(self.width, self.height, &self.pixels)
+    }
+}
+
+// rasterize a level, scaled so that it fits into the given size
+pub fn rasterize(map: &map::Map, max_size: (u32, u32)) -> Canvas {
+    let scale = (max_size.0 as f32 / map.size.0.max(1) as f32).min(max_size.1 as f32 / map.size.1.max(1) as f32);
+    let width = ((map.size.0 as f32 * scale).round() as u32).max(1);
+    let height = ((map.size.1 as f32 * scale).round() as u32).max(1);
+    let background = utils::BACKGROUND_COLOR;
+    let mut canvas = Canvas::new(width, height, (background[0], background[1], background[2]));
+    let to_image = |p: (i32, i32)| (p.0 as f32 * scale, (map.size.1 as i32 - p.1) as f32 * scale);
+
+    for shape in shapes(map) {
+        match shape {
+            Shape::Rect { pos, size, color } => {
+                let (x, y) = to_image(pos);
+                canvas.fill(x, y, x + size.0 as f32 * scale, y + size.1 as f32 * scale, color);
+            }
+            // there is no font rendering, so descriptions are drawn as their box
+            Shape::Text { pos, size, color, .. } => {
+                let (x, y) = to_image(pos);
+                let (w, h) = (size.0 as f32 * scale * 0.5, size.1 as f32 * scale * 0.5);
+                let corners = [(x - w, y - h), (x + w, y - h), (x + w, y + h), (x - w, y + h)];
+                for i in 0..4 {
+                    canvas.line(corners[i], corners[(i + 1) % 4], color, true);
+                }
+            }
+            Shape::Track { points, closed } => {
+                let points: Vec<(f32, f32)> = points.into_iter().map(to_image).collect();
+                let count = if closed { points.len() } else { points.len() - 1 };
+                for i in 0..count {
+                    canvas.line(points[i], points[(i + 1) % points.len()], TRACK_COLOR, true);
+                }
+            }
+            Shape::Arrow { from, to } => {
+                let (from, to) = (to_image(from), to_image(to));
+                canvas.line(from, to, ARROW_COLOR, false);
+                for end in arrow_head(from, to, (12. * scale).max(3.)).iter() {
+                    canvas.line(to, *end, ARROW_COLOR, false);
+                }
+            }
+        }
+    }
+    canvas
+}
+
+// PNG with uncompressed deflate blocks, which every decoder reads
+// nothing is compressed, so the file takes a little more than 4 bytes per pixel (about 7 MB for a 2560x720 map)
+fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    // every row starts with filter type 0 (none)
+    let mut raw = Vec::with_capacity(((width * 4 + 1) * height) as usize);
+    for row in rgba.chunks((width * 4) as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // zlib stream made of stored blocks
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if raw.is_empty() { vec![&[]] } else { raw.chunks(65535).collect() };
+    for (i, block) in blocks.iter().enumerate() {
+        let len = block.len() as u16;
+        zlib.push(if i + 1 == blocks.len() { 1 } else { 0 });
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = vec![];
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);  // 8 bits per channel, RGBA, no interlacing
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    for (kind, data) in [(b"IHDR", header), (b"IDAT", zlib), (b"IEND", vec![])].iter() {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(*kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    png
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn be32(bytes: &[u8]) -> u32 {
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
+
+    // a decoder for what encode_png writes: 8 bit RGBA, stored deflate blocks and no row filters
+    fn decode_png(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let (mut size, mut idat, mut rest) = (None, vec![], &png[8..]);
+        loop {
+            let length = be32(rest) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + length]);
+            assert_eq!(be32(&rest[8 + length..]), crc32(&rest[4..8 + length]), "bad CRC in {:?}", kind);
+            match kind {
+                b"IHDR" => {
+                    assert_eq!(&data[8..], &[8, 6, 0, 0, 0]);
+                    size = Some((be32(data), be32(&data[4..])));
+                }
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => break,
+                _ => panic!("unexpected chunk {:?}", kind),
+            }
+            rest = &rest[12 + length..];
+        }
+        assert_eq!(rest.len(), 12);
+        let (width, height) = size.expect("no IHDR chunk");
+
+        assert_eq!(&idat[..2], &[0x78, 0x01]);
+        let (mut raw, mut block) = (vec![], &idat[2..]);
+        loop {
+            let last = block[0] & 1 == 1;
+            assert_eq!(block[0] >> 1, 0, "only stored blocks are written");
+            let length = u16::from_le_bytes([block[1], block[2]]);
+            assert_eq!(u16::from_le_bytes([block[3], block[4]]), !length);
+            raw.extend_from_slice(&block[5..5 + length as usize]);
+            block = &block[5 + length as usize..];
+            if last { break; }
+        }
+        assert_eq!(block.len(), 4);
+        assert_eq!(be32(block), adler32(&raw));
+
+        let mut pixels = vec![];
+        for row in raw.chunks((width * 4 + 1) as usize) {
+            assert_eq!(row[0], 0);
+            pixels.extend_from_slice(&row[1..]);
+        }
+        assert_eq!(pixels.len(), (width * height * 4) as usize);
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn small_canvases_encode_to_a_known_png() {
+        let mut canvas = Canvas::new(2, 1, (255, 0, 0));
+        canvas.set(1, 0, (0, 0, 255));
+        assert_eq!(canvas.to_png(), vec![
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a,
+            0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01,
+            0x08, 0x06, 0x00, 0x00, 0x00, 0xf4, 0x22, 0x7f, 0x8a,
+            0x00, 0x00, 0x00, 0x14, 0x49, 0x44, 0x41, 0x54, 0x78, 0x01, 0x01, 0x09, 0x00, 0xf6, 0xff,
+            0x00, 0xff, 0x00, 0x00, 0xff, 0x00, 0x00, 0xff, 0xff, 0x0f, 0xf9, 0x03, 0xfd, 0xa6, 0x2e, 0x70, 0x51,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82,
+        ]);
+    }
+
+    #[test]
+    fn rasterized_levels_decode_to_their_pixels() {
+        let mut map = map::Map::new((8, 4));
+        map.player.pos = (0, 4);
+        map.player.size = (2, 2);
+        map.player.color = (255, 0, 0);
+        map.obstacles.push(map::Obstacle {
+            name: "o".to_string(), pos: (4, 2), size: (4, 2), color: (0, 0, 255), one_way: false,
+        });
+        let canvas = rasterize(&map, (8, 4));
+        let (width, height, pixels) = decode_png(&canvas.to_png());
+        assert_eq!((width, height), (8, 4));
+        assert_eq!(pixels, canvas.pixels);
+
+        let pixel = |x: usize, y: usize| &pixels[(y * 8 + x) * 4..(y * 8 + x + 1) * 4];
+        assert_eq!(pixel(1, 1), &[255, 0, 0, 255]);
+        assert_eq!(pixel(5, 3), &[0, 0, 255, 255]);
+        assert_eq!(pixel(5, 0), &[60, 179, 113, 255]);
+        assert_eq!(pixel(1, 3), &[60, 179, 113, 255]);
+    }
+
+    #[test]
+    fn svg_has_rectangles_tracks_and_arrows() {
+        let mut map = map::Map::new((100, 50));
+        map.player.pos = (0, 50);
+        map.player.size = (5, 5);
+        map.player.color = (255, 0, 0);
+        map.obstacles.push(map::Obstacle {
+            name: "o".to_string(), pos: (60, 20), size: (10, 10), color: (0, 0, 255), one_way: false,
+        });
+        map.elevators.push(map::Elevator {
+            name: "e".to_string(),
+            track: vec![map::Track { pos: (20, 40), speed: (2, 0) }, map::Track { pos: (40, 40), speed: (-2, 0) }],
+            path: None, size: (10, 10), color: (128, 128, 128), one_way: false,
+        });
+        let mut command = HashMap::new();
+        command.insert("close".to_string(), vec![vec!["remove".to_string(), "o".to_string()]]);
+        map.switches.push(map::Switch { name: "s".to_string(), pos: (10, 20), size: (10, 10), color: (0, 255, 0), command });
+        let svg = to_svg(&map);
+
+        // y is flipped, the map counts up from the bottom and SVG down from the top
+        for line in [
+            "<rect width=\"100\" height=\"50\" fill=\"rgb(60,179,113)\"/>",
+            "<rect x=\"0\" y=\"0\" width=\"5\" height=\"5\" fill=\"rgb(255,0,0)\"/>",
+            "<rect x=\"60\" y=\"30\" width=\"10\" height=\"10\" fill=\"rgb(0,0,255)\"/>",
+            "<rect x=\"20\" y=\"10\" width=\"10\" height=\"10\" fill=\"rgb(128,128,128)\"/>",
+            "<rect x=\"10\" y=\"30\" width=\"10\" height=\"10\" fill=\"rgb(0,255,0)\"/>",
+            "<polygon points=\"25,15 45,15\" fill=\"none\" stroke=\"rgb(255,255,255)\"",
+            "<line x1=\"15\" y1=\"35\" x2=\"65\" y2=\"35\" stroke=\"rgb(0,0,192)\" stroke-width=\"2\"/>",
+            "<polygon points=\"65,35 ",
+        ].iter() {
+            assert!(svg.contains(line), "{} is missing from\n{}", line, svg);
+        }
+        assert!(svg.starts_with("<svg ") && svg.ends_with("</svg>\n"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::levels;
+use crate::utils;
+
+// bump when the format changes and add the conversion to Profile::migrate
+pub const SAVE_VERSION: u32 = 1;
+
+// Save profile: progress of the player over all level packs
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Profile {
+    pub version: u32,
+    #[serde(default)]
+    pub levels: HashMap<String, LevelRecord>,  // keyed by "<pack id>/<level id>"
+    #[serde(default)]
+    pub total_coins: i32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct LevelRecord {
+    #[serde(default)]
+    pub unlocked: bool,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub best_score: Option<i32>,
+    #[serde(default)]
+    pub best_time: Option<u32>,  // in fixed update ticks
+    #[serde(default)]
+    pub best_splits: Vec<Split>,  // splits of the best time run
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct Split {
+    pub name: String,
+    pub ticks: u32,
+}
+
+// The result of one attempt at a level
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct Run {
+    pub score: i32,
+    pub ticks: u32,
+    pub splits: Vec<Split>,
+}
+
+// Which records a finished run has broken
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunResult {
+    pub best_score: bool,
+    pub best_time: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile { version: SAVE_VERSION, levels: HashMap::new(), total_coins: 0 }
+    }
+}
+
+impl Profile {
+    pub fn path() -> PathBuf {
+        utils::data_dir().join("save.ron")
+    }
+
+    pub fn key(level: &levels::Level) -> String {
+        level.pack.id.clone() + "/" + &level.info.id
+    }
+
+    pub fn load() -> Profile {
+        Profile::load_from(&Profile::path())
+    }
+
+    // a missing save starts a new profile
+    // a broken save is moved aside so that it is not overwritten
+    fn load_from(path: &PathBuf) -> Profile {
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => { return Profile::default(); }
+        };
+        match ron::from_str::<Profile>(contents.as_str()).map_err(|err| err.to_string()).and_then(Profile::migrate) {
+            Ok(profile) => profile,
+            Err(err) => {
+                log::error!("Failed to load save file \"{}\": {}", path.display(), err);
+                std::fs::rename(&path, path.with_extension("ron.bak")).unwrap_or(());
+                Profile::default()
+            }
+        }
+    }
+
+    // check the version of a loaded save
+    // version 1 is the first format, older versions will be converted here one at a time once the format changes
+    // a newer save is rejected, so that it is moved aside instead of being overwritten with less data
+    fn migrate(self) -> Result<Profile, String> {
+        match self.version {
+            SAVE_VERSION => Ok(self),
+            version if version > SAVE_VERSION => {
+                Err(format!("version {} is newer than the supported version {}", version, SAVE_VERSION))
+            }
+            version => Err(format!("version {} is not supported", version)),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        self.save_to(&Profile::path())
+    }
+
+    fn save_to(&self, path: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        // write to a temporary file first so that a crash cannot corrupt the save
+        let tmp_path = path.with_extension("ron.tmp");
+        std::fs::write(&tmp_path, contents)?;
+        std::fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn record(&self, level: &levels::Level) -> LevelRecord {
+        self.levels.get(&Profile::key(level)).cloned().unwrap_or_default()
+    }
+
+    // the first level of every pack is always unlocked
+    pub fn is_unlocked(&self, level: &levels::Level) -> bool {
+        level.pack.first_level_id() == Some(&level.info.id) || self.record(level).unlocked
+    }
+
+    // record the end of a run
+    // replays only add the coins beyond the best score of the level, so total_coins cannot be farmed
+    pub fn record_run(&mut self, level: &levels::Level, won: bool, run: &Run) -> RunResult {
+        if !won { return RunResult::default(); }
+
+        let record = self.levels.entry(Profile::key(level)).or_default();
+        self.total_coins += (run.score - record.best_score.unwrap_or(0)).max(0);
+        record.unlocked = true;
+        record.completed = true;
+        let result = RunResult {
+            best_score: record.best_score.map_or(true, |best| run.score > best),
+            best_time: record.best_time.map_or(true, |best| run.ticks < best),
+        };
+        if result.best_score { record.best_score = Some(run.score); }
+        if result.best_time {
+            record.best_time = Some(run.ticks);
+            record.best_splits = run.splits.clone();
+        }
+
+        // unlock the next level in the pack
+        if let Some(next_id) = level.pack.next_level_id(&level.info.id) {
+            let key = level.pack.id.clone() + "/" + next_id;
+            self.levels.entry(key).or_default().unlocked = true;
+        }
+        result
+    }
+}
+
+// write the splits of a run to <data dir>/splits/<pack id>/<level id>.ron
+pub fn export_splits(level: &levels::Level, run: &Run) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let dir = utils::data_dir().join("splits").join(&level.pack.id);
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(level.info.id.clone() + ".ron");
+    std::fs::write(&path, ron::ser::to_string_pretty(run, ron::ser::PrettyConfig::default())?)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("platformer-save-{}-{}", name, std::process::id())).join("save.ron")
+    }
+
+    fn pack(ids: &[&str]) -> levels::Pack {
+        levels::Pack {
+            id: "pack".to_string(),
+            levels: ids.iter().map(|id| levels::LevelInfo { id: id.to_string(), ..Default::default() }).collect(),
+            valid: true,
+            ..Default::default()
+        }
+    }
+
+    fn level(pack: &levels::Pack, id: &str) -> levels::Level {
+        let info = pack.levels.iter().find(|info| info.id == id).unwrap().clone();
+        levels::Level { pack: pack.clone(), info, path: String::new(), valid: true }
+    }
+
+    fn run(score: i32, ticks: u32) -> Run {
+        Run { score, ticks, splits: vec![] }
+    }
+
+    #[test]
+    fn saves_are_loaded_back() {
+        let path = temp_path("roundtrip");
+        let mut profile = Profile::default();
+        profile.total_coins = 7;
+        profile.save_to(&path).unwrap();
+        assert_eq!(Profile::load_from(&path).total_coins, 7);
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap_or(());
+    }
+
+    #[test]
+    fn only_the_current_version_is_supported() {
+        assert!(Profile::default().migrate().is_ok());
+        let newer = Profile { version: SAVE_VERSION + 1, ..Default::default() };
+        assert!(newer.migrate().unwrap_err().contains("newer"));
+        let older = Profile { version: 0, ..Default::default() };
+        assert!(older.migrate().is_err());
+    }
+
+    #[test]
+    fn broken_saves_are_moved_aside() {
+        let path = temp_path("broken");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "(version: 1, levels: {").unwrap();
+        let profile = Profile::load_from(&path);
+        assert_eq!(profile.version, SAVE_VERSION);
+        assert!(profile.levels.is_empty());
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(path.with_extension("ron.bak")).unwrap(), "(version: 1, levels: {");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap_or(());
+    }
+
+    #[test]
+    fn newer_saves_are_moved_aside() {
+        let path = temp_path("newer");
+        Profile { version: SAVE_VERSION + 1, ..Default::default() }.save_to(&path).unwrap();
+        assert_eq!(Profile::load_from(&path).version, SAVE_VERSION);
+        assert!(path.with_extension("ron.bak").exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap_or(());
+    }
+
+    #[test]
+    fn winning_unlocks_the_next_level() {
+        let pack = pack(&["a", "b", "c"]);
+        let mut profile = Profile::default();
+        assert!(profile.is_unlocked(&level(&pack, "a")));
+        assert!(!profile.is_unlocked(&level(&pack, "b")));
+
+        profile.record_run(&level(&pack, "a"), false, &run(3, 100));
+        assert!(!profile.is_unlocked(&level(&pack, "b")));
+
+        profile.record_run(&level(&pack, "a"), true, &run(3, 100));
+        assert!(profile.record(&level(&pack, "a")).completed);
+        assert!(profile.is_unlocked(&level(&pack, "b")));
+        assert!(!profile.is_unlocked(&level(&pack, "c")));
+    }
+
+    #[test]
+    fn replays_only_add_coins_beyond_the_best_score() {
+        let pack = pack(&["a"]);
+        let mut profile = Profile::default();
+        let result = profile.record_run(&level(&pack, "a"), true, &run(3, 100));
+        assert!(result.best_score && result.best_time);
+        assert_eq!(profile.total_coins, 3);
+
+        let result = profile.record_run(&level(&pack, "a"), true, &run(3, 90));
+        assert!(!result.best_score && result.best_time);
+        assert_eq!(profile.total_coins, 3);
+
+        profile.record_run(&level(&pack, "a"), true, &run(5, 120));
+        assert_eq!(profile.total_coins, 5);
+        assert_eq!(profile.record(&level(&pack, "a")).best_time, Some(90));
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{ BTreeMap, VecDeque };
+use serde::{Deserialize, Serialize};
+use amethyst::{
+    ecs::{ Entity, Join },
+    prelude::*,
+    ui::{ UiImage, UiTransform },
+};
+
+use crate::components;
+use crate::map;
+use crate::physics;
+use crate::utils;
+
+// Snapshot: all mutable gameplay state of a level
+// positions are stored unscaled, like the physics uses them
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    pub player: Option<PlayerState>,
+    pub movables: BTreeMap<String, MovableState>,
+    pub states: BTreeMap<String, String>,  // states of all interactables
+    pub removed: Vec<String>,  // objects of the map that no longer exist
+    pub colors: BTreeMap<String, [f32; 4]>,
+    pub score: i32,
+    #[serde(default)]
+    pub ticks: u32,  // time of the run, which goes back when rewinding
+    #[serde(default)]
+    pub timers: BTreeMap<String, u32>,  // cooldowns of springs
+    #[serde(default)]
+    pub enemies: BTreeMap<String, EnemyState>,
+    #[serde(default)]
+    pub turrets: BTreeMap<String, components::TurretComp>,
+    #[serde(default)]
+    pub projectiles: BTreeMap<String, ProjectileState>,  // projectiles in flight
+    #[serde(default)]
+    pub spawned: BTreeMap<String, map::Object>,  // objects created by spawn commands
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PlayerState {
+    pub pos: (i32, i32),
+    pub comp: components::PlayerComp,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MovableState {
+    pub pos: (i32, i32),
+    pub speed: (i32, i32),
+    pub update_speed: bool,
+    #[serde(default)]
+    pub tick: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EnemyState {
+    pub pos: (i32, i32),
+    pub comp: components::EnemyComp,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ProjectileState {
+    pub pos: (i32, i32),
+    pub comp: components::ProjectileComp,
+}
+
+// Delta: the parts of a snapshot that differ from the next one
+// applying it to the later snapshot gives back the earlier one
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Delta {
+    pub player: Option<Option<PlayerState>>,
+    pub movables: MapDelta<MovableState>,
+    pub states: MapDelta<String>,
+    pub removed: Option<Vec<String>>,
+    pub colors: MapDelta<[f32; 4]>,
+    pub score: Option<i32>,
+    pub ticks: Option<u32>,
+    pub timers: MapDelta<u32>,
+    pub enemies: MapDelta<EnemyState>,
+    pub turrets: MapDelta<components::TurretComp>,
+    pub projectiles: Option<BTreeMap<String, ProjectileState>>,
+    pub spawned: Option<BTreeMap<String, map::Object>>,
+}
+
+// The entries of a map that differ in the earlier snapshot
+// objects can disappear (e.g. a spawned movable that is removed), so keys missing from the earlier map are kept too
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MapDelta<V> {
+    pub changed: BTreeMap<String, V>,
+    pub removed: Vec<String>,
+}
+
+impl<V> Default for MapDelta<V> {
+    fn default() -> Self {
+        MapDelta { changed: BTreeMap::new(), removed: vec![] }
+    }
+}
+
+impl<V: Clone + PartialEq> MapDelta<V> {
+    fn new(later: &BTreeMap<String, V>, earlier: &BTreeMap<String, V>) -> Self {
+        MapDelta {
+            changed: earlier.iter()
+                .filter(|(name, value)| later.get(*name) != Some(*value))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            removed: later.keys().filter(|name| !earlier.contains_key(*name)).cloned().collect(),
+        }
+    }
+
+    fn apply(&self, map: &mut BTreeMap<String, V>) {
+        for name in self.removed.iter() {
+            map.remove(name);
+        }
+        map.extend(self.changed.iter().map(|(name, value)| (name.clone(), value.clone())));
+    }
+}
+
+// History: the last ticks of play, kept as a ring buffer of deltas
+#[derive(Default)]
+pub struct History {
+    capacity: usize,  // number of ticks that can be rewound
+    current: Option<Snapshot>,
+    deltas: VecDeque<Delta>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History { capacity, current: None, deltas: VecDeque::new() }
+    }
+
+    pub fn record(&mut self, snapshot: Snapshot) {
+        if let Some(current) = &self.current {
+            self.deltas.push_back(snapshot.diff(current));
+            if self.deltas.len() > self.capacity { self.deltas.pop_front(); }
+        }
+        self.current = Some(snapshot);
+    }
+
+    // go back by one tick, returns the state to restore
+    pub fn rewind(&mut self) -> Option<&Snapshot> {
+        let delta = self.deltas.pop_back()?;
+        let current = self.current.as_mut()?;
+        current.apply(&delta);
+        Some(current)
+    }
+
+    pub fn clear(&mut self) {
+        self.current = None;
+        self.deltas.clear();
+    }
+}
+
+fn unscaled_pos(uitrans: &UiTransform) -> (i32, i32) {
+    ((uitrans.local_x / utils::dpi()) as i32, (uitrans.local_y / utils::dpi()) as i32)
+}
+
+impl Snapshot {
+    pub fn capture(world: &World, map: &map::Map, score: i32) -> Self {
+        let uitrans_store = world.read_storage::<UiTransform>();
+        let obj_store = world.read_storage::<components::ObjectComp>();
+        let player_store = world.read_storage::<components::PlayerComp>();
+        let movable_store = world.read_storage::<components::MovableComp>();
+        let inter_store = world.read_storage::<components::InteractableComp>();
+        let img_store = world.read_storage::<UiImage>();
+        let spring_store = world.read_storage::<components::SpringComp>();
+        let enemy_store = world.read_storage::<components::EnemyComp>();
+        let turret_store = world.read_storage::<components::TurretComp>();
+        let projectile_store = world.read_storage::<components::ProjectileComp>();
+        let spawned_store = world.read_storage::<components::SpawnedComp>();
+
+        let player = (&uitrans_store, &player_store).join().next()
+            .map(|(uitrans, player)| PlayerState { pos: unscaled_pos(uitrans), comp: player.clone() });
+        let movables = (&uitrans_store, &movable_store).join()
+            .map(|(uitrans, movable)| (movable.name.clone(), MovableState {
+                pos: unscaled_pos(uitrans), speed: movable.speed, update_speed: movable.update_speed,
+                tick: movable.tick,
+            }))
+            .collect();
+        let states = (&inter_store).join()
+            .map(|inter| (inter.name.clone(), inter.state.clone()))
+            .collect();
+        let present: Vec<String> = (&obj_store).join().map(|obj| obj.name.clone()).collect();
+        let removed = map.object_names().into_iter().filter(|name| !present.contains(name)).collect();
+        let mut colors = BTreeMap::new();
+        for (obj, img) in (&obj_store, &img_store).join() {
+            if let UiImage::SolidColor(color) = img {
+                colors.insert(obj.name.clone(), *color);
+            }
+        }
+
+        let timers = (&spring_store).join()
+            .map(|spring| (spring.name.clone(), spring.timer))
+            .collect();
+
+        let enemies = (&uitrans_store, &enemy_store).join()
+            .map(|(uitrans, enemy)| (enemy.name.clone(), EnemyState { pos: unscaled_pos(uitrans), comp: enemy.clone() }))
+            .collect();
+
+        let turrets = (&turret_store).join()
+            .map(|turret| (turret.name.clone(), turret.clone()))
+            .collect();
+        let projectiles = (&uitrans_store, &projectile_store).join()
+            .map(|(uitrans, projectile)| (projectile.name.clone(), ProjectileState {
+                pos: unscaled_pos(uitrans), comp: projectile.clone(),
+            }))
+            .collect();
+
+        let spawned = (&spawned_store).join()
+            .map(|spawned| (spawned.object.name().clone(), spawned.object.clone()))
+            .collect();
+
+        Snapshot {
+            player, movables, states, removed, colors, score, ticks: 0, timers, enemies, turrets, projectiles, spawned,
+        }
+    }
+
+    // the score and the ticks are not part of the world, so the caller has to restore them
+    pub fn restore(&self, world: &mut World, map: &map::Map) {
+        // bring back the objects removed after the snapshot and remove those removed before
+        let present: Vec<String> = (&world.read_storage::<components::ObjectComp>()).join()
+            .map(|obj| obj.name.clone()).collect();
+        for name in map.object_names().iter() {
+            if !present.contains(name) && !self.removed.contains(name) {
+                map.initialize_named(name, world);
+            }
+        }
+        physics::remove_objs(world, self.removed.clone());
+
+        // replace the spawned objects, their state is restored below like for the other objects
+        let spawned: Vec<Entity> = (&world.entities(), &world.read_storage::<components::SpawnedComp>()).join()
+            .map(|(ent, _)| ent).collect();
+        for ent in spawned.into_iter() {
+            world.delete_entity(ent).expect("Entity does not exist");
+        }
+        for object in self.spawned.values() {
+            map::spawn(world, object);
+        }
+
+        // replace the projectiles in flight
+        let projectiles: Vec<String> = (&world.read_storage::<components::ProjectileComp>()).join()
+            .map(|projectile| projectile.name.clone()).collect();
+        physics::remove_objs(world, projectiles);
+        if let Some(map_ent) = map::find_map_entity(world) {
+            for state in self.projectiles.values() {
+                state.comp.projectile.spawn(world, &map_ent,
+                    state.comp.name.clone(), state.comp.turret.clone(), state.pos, state.comp.lifetime);
+            }
+        }
+
+        let mut uitrans_store = world.write_storage::<UiTransform>();
+        let obj_store = world.read_storage::<components::ObjectComp>();
+        let mut player_store = world.write_storage::<components::PlayerComp>();
+        let mut movable_store = world.write_storage::<components::MovableComp>();
+        let mut inter_store = world.write_storage::<components::InteractableComp>();
+        let mut img_store = world.write_storage::<UiImage>();
+        let mut spring_store = world.write_storage::<components::SpringComp>();
+        let mut enemy_store = world.write_storage::<components::EnemyComp>();
+        let mut turret_store = world.write_storage::<components::TurretComp>();
+
+        // player
+        if let Some(state) = &self.player {
+            for (uitrans, player) in (&mut uitrans_store, &mut player_store).join() {
+                uitrans.local_x = state.pos.0 as f32 * utils::dpi();
+                uitrans.local_y = state.pos.1 as f32 * utils::dpi();
+                *player = state.comp.clone();
+            }
+        }
+
+        // movables
+        for (uitrans, movable) in (&mut uitrans_store, &mut movable_store).join() {
+            if let Some(state) = self.movables.get(&movable.name) {
+                uitrans.local_x = state.pos.0 as f32 * utils::dpi();
+                uitrans.local_y = state.pos.1 as f32 * utils::dpi();
+                movable.speed = state.speed;
+                movable.update_speed = state.update_speed;
+                movable.tick = state.tick;
+            }
+        }
+
+        // enemies
+        for (uitrans, enemy) in (&mut uitrans_store, &mut enemy_store).join() {
+            if let Some(state) = self.enemies.get(&enemy.name) {
+                uitrans.local_x = state.pos.0 as f32 * utils::dpi();
+                uitrans.local_y = state.pos.1 as f32 * utils::dpi();
+                *enemy = state.comp.clone();
+            }
+        }
+
+        // turrets
+        for turret in (&mut turret_store).join() {
+            if let Some(state) = self.turrets.get(&turret.name) {
+                *turret = state.clone();
+            }
+        }
+
+        // states and colors
+        for inter in (&mut inter_store).join() {
+            if let Some(state) = self.states.get(&inter.name) {
+                inter.state = state.clone();
+            }
+        }
+        for (obj, img) in (&obj_store, &mut img_store).join() {
+            if let (Some(saved), UiImage::SolidColor(color)) = (self.colors.get(&obj.name), img) {
+                *color = *saved;
+            }
+        }
+        for spring in (&mut spring_store).join() {
+            if let Some(timer) = self.timers.get(&spring.name) {
+                spring.timer = *timer;
+            }
+        }
+    }
+
+    // the changes that turn this snapshot into an earlier one
+    pub fn diff(&self, earlier: &Snapshot) -> Delta {
+        let mut delta = Delta::default();
+        if self.player != earlier.player { delta.player = Some(earlier.player.clone()); }
+        delta.movables = MapDelta::new(&self.movables, &earlier.movables);
+        delta.states = MapDelta::new(&self.states, &earlier.states);
+        if self.removed != earlier.removed { delta.removed = Some(earlier.removed.clone()); }
+        delta.colors = MapDelta::new(&self.colors, &earlier.colors);
+        if self.score != earlier.score { delta.score = Some(earlier.score); }
+        if self.ticks != earlier.ticks { delta.ticks = Some(earlier.ticks); }
+        delta.timers = MapDelta::new(&self.timers, &earlier.timers);
+        delta.enemies = MapDelta::new(&self.enemies, &earlier.enemies);
+        delta.turrets = MapDelta::new(&self.turrets, &earlier.turrets);
+        if self.projectiles != earlier.projectiles { delta.projectiles = Some(earlier.projectiles.clone()); }
+        if self.spawned != earlier.spawned { delta.spawned = Some(earlier.spawned.clone()); }
+        delta
+    }
+
+    pub fn apply(&mut self, delta: &Delta) {
+        if let Some(player) = &delta.player { self.player = player.clone(); }
+        delta.movables.apply(&mut self.movables);
+        delta.states.apply(&mut self.states);
+        if let Some(removed) = &delta.removed { self.removed = removed.clone(); }
+        delta.colors.apply(&mut self.colors);
+        if let Some(score) = delta.score { self.score = score; }
+        if let Some(ticks) = delta.ticks { self.ticks = ticks; }
+        delta.timers.apply(&mut self.timers);
+        delta.enemies.apply(&mut self.enemies);
+        delta.turrets.apply(&mut self.turrets);
+        if let Some(projectiles) = &delta.projectiles { self.projectiles = projectiles.clone(); }
+        if let Some(spawned) = &delta.spawned { self.spawned = spawned.clone(); }
+    }
+
+    pub fn to_ron(&self) -> Result<String, ron::Error> {
+        ron::to_string(self)
+    }
+
+    pub fn from_ron(s: &str) -> Result<Snapshot, ron::Error> {
+        ron::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot;
+
+    fn level() -> map::Map {
+        map::from_file("assets/levels/3.ron".to_string()).unwrap()
+    }
+
+    // run right, jumping now and then, so that coins, switches and monsters are reached
+    fn inputs(ticks: usize) -> Vec<physics::Input> {
+        (0..ticks).map(|tick| physics::Input { right: true, jump: tick % 40 < 10, ..Default::default() }).collect()
+    }
+
+    fn run(sim: &mut bot::Sim, inputs: &[physics::Input]) -> Vec<Snapshot> {
+        inputs.iter().map(|input| {
+            sim.step(input);
+            sim.save()
+        }).collect()
+    }
+
+    #[test]
+    fn restore_then_replay_matches_an_uninterrupted_run() {
+        let inputs = inputs(240);
+        let mut sim = bot::Sim::new(level());
+        run(&mut sim, &inputs[..120]);
+        let saved = sim.save();
+        let uninterrupted = run(&mut sim, &inputs[120..]);
+
+        // in the same world, after it moved on
+        sim.load(&saved);
+        assert_eq!(run(&mut sim, &inputs[120..]), uninterrupted);
+
+        // in a new world
+        let mut other = bot::Sim::new(level());
+        other.load(&saved);
+        assert_eq!(run(&mut other, &inputs[120..]), uninterrupted);
+    }
+
+    #[test]
+    fn applying_a_diff_gives_the_earlier_snapshot() {
+        let mut sim = bot::Sim::new(level());
+        let snapshots = run(&mut sim, &inputs(240));
+        for pair in snapshots.windows(2) {
+            let (earlier, later) = (&pair[0], &pair[1]);
+            let mut restored = later.clone();
+            restored.apply(&later.diff(earlier));
+            assert_eq!(&restored, earlier);
+        }
+    }
+
+    #[test]
+    fn history_rewinds_every_tick() {
+        let mut sim = bot::Sim::new(level());
+        let snapshots = run(&mut sim, &inputs(120));
+        let mut history = History::new(snapshots.len());
+        for snapshot in snapshots.iter() {
+            history.record(snapshot.clone());
+        }
+        for earlier in snapshots.iter().rev().skip(1) {
+            assert_eq!(history.rewind(), Some(earlier));
+        }
+        assert_eq!(history.rewind(), None);
+    }
+
+    #[test]
+    fn applying_a_diff_removes_entries_added_later() {
+        let movable = MovableState { pos: (0, 0), speed: (1, 0), update_speed: true, tick: 0 };
+        let mut earlier = Snapshot::default();
+        earlier.movables.insert("e1".to_string(), movable.clone());
+        let mut later = earlier.clone();
+        later.movables.insert("spawned".to_string(), movable.clone());
+        later.states.insert("spawned".to_string(), "close".to_string());
+        later.timers.insert("spawned".to_string(), 3);
+
+        let mut restored = later.clone();
+        restored.apply(&later.diff(&earlier));
+        assert_eq!(restored, earlier);
+    }
+}
@@ -1,18 +1,23 @@
-use std::collections::HashMap;
 use amethyst::{
     ecs::{ WorldExt, Join, Entity },
     input::{ InputHandler, StringBindings, InputEvent, VirtualKeyCode, is_key_down },
     prelude::*,
-    ui::{ Anchor, UiTransform },
-    utils::application_root_dir,
+    ui::UiTransform,
     window::ScreenDimensions,
     winit::MouseButton,
 };
 
-use crate::lib::components;
-use crate::lib::entities;
-use crate::lib::map;
-use crate::lib::utils;
+use crate::components;
+use crate::editor;
+use crate::entities;
+use crate::fonts;
+use crate::levels;
+use crate::map;
+use crate::physics;
+use crate::render;
+use crate::save;
+use crate::snapshot;
+use crate::utils;
 
 // Init: initialize the environment
 #[derive(Default)]
@@ -24,14 +29,7 @@ impl SimpleState for InitState {
         utils::set_dpi(data.world.read_resource::<ScreenDimensions>().height() / 720.);
 
         // register the components
-        data.world.register::<components::CameraComp>();
-        data.world.register::<components::MapComp>();
-        data.world.register::<components::PlayerComp>();
-        data.world.register::<components::ObjectComp>();
-        data.world.register::<components::MovableComp>();
-        data.world.register::<components::CollidableComp>();
-        data.world.register::<components::InteractableComp>();
-        data.world.register::<components::DescriptionComp>();
+        components::register(data.world);
 
         // camera only need to be initialized once
         entities::init_camera(&mut data.world);
@@ -47,6 +45,7 @@ impl SimpleState for InitState {
 #[derive(Default)]
 pub struct MenuState {
     b_level: Option<entities::Button>,  // new game button data (not the actual entity)
+    b_editor: Option<entities::Button>,  // level editor button data
     b_exit: Option<entities::Button>  // exit button data
 }
 
@@ -64,10 +63,14 @@ impl SimpleState for MenuState {
             "New Game".to_string(), 300., 60.,
             "merriweather.ttf".to_string(), 30.));
         self.b_level.as_mut().unwrap().instantiate("level".to_string(), data.world, 0., 0., 1.);
+        self.b_editor = Some(entities::Button::default(
+            "Level Editor".to_string(), 300., 60.,
+            "merriweather.ttf".to_string(), 30.));
+        self.b_editor.as_mut().unwrap().instantiate("editor".to_string(), data.world, 0., -100., 1.);
         self.b_exit = Some(entities::Button::default(
             "Exit".to_string(), 300., 60.,
             "merriweather.ttf".to_string(), 30.));
-        self.b_exit.as_mut().unwrap().instantiate("exit".to_string(), data.world, 0., -120., 1.);
+        self.b_exit.as_mut().unwrap().instantiate("exit".to_string(), data.world, 0., -200., 1.);
     }
 
     fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
@@ -88,7 +91,15 @@ impl SimpleState for MenuState {
                     if let Some(btn) = &self.b_level {
                         if btn.in_range(x, y) {
                             data.world.delete_all();
-                            return Trans::Replace(Box::new(LevelState::new(8)));
+                            return Trans::Replace(Box::new(PackState::default()));
+                        }
+                    }
+
+                    // if user clicks level editor button
+                    if let Some(btn) = &self.b_editor {
+                        if btn.in_range(x, y) {
+                            data.world.delete_all();
+                            return Trans::Replace(Box::new(editor::EditorState::new(levels::editor_level())));
                         }
                     }
 
@@ -106,50 +117,147 @@ impl SimpleState for MenuState {
     }
 }
 
-// Level
+// position of a button in the 4x2 selection grid
+fn grid_pos(slot: usize) -> (f32, f32) {
+    let center = [0., 0.];
+    let dist = [200., 160.];
+    let x = center[0] + ((slot % 4) as f32 - 1.5) * dist[0];
+    let y = center[1] - ((slot / 4) as f32 - 0.5) * dist[1];
+    (x, y)
+}
+
+// a button in the selection grid, shown in gray if it cannot be selected
+fn grid_button(text: String, enabled: bool) -> entities::Button {
+    if enabled {
+        entities::Button::default(text, 160., 100., "merriweather-b.ttf".to_string(), 24.)
+    } else {
+        entities::Button::new(
+            text, 160., 100., utils::get_color(utils::GRAY_3),
+            utils::get_color(utils::GRAY_2), utils::get_color(utils::GRAY_3),
+            "merriweather-b.ttf".to_string(), 24.)
+    }
+}
+
+// What a click on a selection grid did
+enum GridClick {
+    Menu,  // the back button
+    Page,  // the page was turned, so the page needs to be built again
+    Item(usize),
+    None,
+}
+
+// A paged grid of selection buttons with back and page buttons, used to select packs and levels
 #[derive(Default)]
-pub struct LevelState {
-    num_levels: usize,  // total number of levels (which is 8)
+struct SelectionGrid {
+    len: usize,  // the number of items on all pages
+    page: usize,  // the page of items currently shown
     b_menu: Option<entities::Button>,  // back button data (not the actual entity)
-    b_games: Vec<Option<entities::Button>>,  // all 8 level selection buttons
+    b_prev: Option<entities::Button>,  // previous page button data
+    b_next: Option<entities::Button>,  // next page button data
+    b_items: Vec<(usize, entities::Button)>,  // selection buttons of the current page and their items
 }
 
-impl LevelState {
-    fn new(num_levels: usize) -> Self {
-        LevelState { num_levels, b_menu: None, b_games: vec![] }
+impl SelectionGrid {
+    fn new(len: usize) -> Self {
+        SelectionGrid { len, ..Default::default() }
     }
-}
 
-impl SimpleState for LevelState {
-    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
-        self.num_levels = 8;
+    fn num_pages(&self) -> usize {
+        ((self.len + utils::LEVELS_PER_PAGE - 1) / utils::LEVELS_PER_PAGE).max(1)
+    }
 
-        // instantiate the background and the title
-        entities::Background::default().instantiate("background".to_string(), data.world);
-        entities::Label::default(
-            "Platformer with RUST".to_string(), 600., 150., "cambria.ttf".to_string(), 60., )
-            .instantiate("title".to_string(), data.world, 0., 200., 1.);
+    // the items of the current page
+    fn items(&self) -> std::ops::Range<usize> {
+        let first = self.page * utils::LEVELS_PER_PAGE;
+        first..(first + utils::LEVELS_PER_PAGE).min(self.len)
+    }
+
+    // instantiate the background, the title, the back and page buttons and the page number
+    // the items are added with add_item
+    fn build(&mut self, world: &mut World, title: String) {
+        entities::Background::default().instantiate("background".to_string(), world);
+        entities::Label::default(title, 600., 150., "cambria.ttf".to_string(), 60., )
+            .instantiate("title".to_string(), world, 0., 200., 1.);
 
-        // instantiate the buttons
         self.b_menu = Some(entities::Button::default(
             "Back".to_string(), 300., 60., "merriweather.ttf".to_string(), 30.));
-        self.b_menu.as_mut().unwrap().instantiate("menu".to_string(), data.world, 0., -200., 1.);
-        let center = [0., 0.];
-        let dist = [200., 160.];
-        for level in 1..(self.num_levels + 1) {
-            let x = center[0] + (((level - 1) % 4) as f32 - 1.5) * dist[0];
-            let y = center[1] - (((level - 1) / 4) as f32 - 0.5) * dist[1];
-            let mut b_game = Some(entities::Button::default(
-                level.to_string(), 70., 100., "merriweather-b.ttf".to_string(), 30.));
-            b_game.as_mut().unwrap().instantiate("exit".to_string(), data.world, x, y, 1.);
-            self.b_games.push(b_game);
-        }
-
-        // A note
-        entities::Label::default(
-            "*Note: currently only the first 3 levels are made".to_string(),
-            800., 150., "merriweather.ttf".to_string(), 24.,)
-                .instantiate("title".to_string(), data.world, 0., -270., 1.);
+        self.b_menu.as_mut().unwrap().instantiate("menu".to_string(), world, 0., -200., 1.);
+        self.b_prev = None;
+        self.b_next = None;
+        if self.page > 0 {
+            self.b_prev = Some(entities::Button::default(
+                "<".to_string(), 100., 60., "merriweather.ttf".to_string(), 30.));
+            self.b_prev.as_mut().unwrap().instantiate("prev".to_string(), world, -300., -200., 1.);
+        }
+        if self.page + 1 < self.num_pages() {
+            self.b_next = Some(entities::Button::default(
+                ">".to_string(), 100., 60., "merriweather.ttf".to_string(), 30.));
+            self.b_next.as_mut().unwrap().instantiate("next".to_string(), world, 300., -200., 1.);
+        }
+        self.b_items = vec![];
+
+        if self.num_pages() > 1 {
+            entities::Label::default(
+                format!("Page {}/{}", self.page + 1, self.num_pages()),
+                800., 150., "merriweather.ttf".to_string(), 24.,)
+                    .instantiate("page".to_string(), world, 0., -270., 1.);
+        }
+    }
+
+    // instantiate the button of an item of the current page, returns its position
+    fn add_item(&mut self, world: &mut World, index: usize, id: String, text: String, enabled: bool) -> (f32, f32) {
+        let (x, y) = grid_pos(index - self.items().start);
+        let mut button = grid_button(text, enabled);
+        button.instantiate(id, world, x, y, 1.);
+        self.b_items.push((index, button));
+        (x, y)
+    }
+
+    // find the button at the position (in game coords), turning the page if needed
+    fn click(&mut self, x: f32, y: f32) -> GridClick {
+        if self.b_menu.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            return GridClick::Menu;
+        }
+        if self.b_prev.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            self.page -= 1;
+            return GridClick::Page;
+        }
+        if self.b_next.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+            self.page += 1;
+            return GridClick::Page;
+        }
+        match self.b_items.iter().find(|(_, btn)| btn.in_range(x, y)) {
+            Some((index, _)) => GridClick::Item(*index),
+            None => GridClick::None,
+        }
+    }
+}
+
+// Pack: select a level pack
+#[derive(Default)]
+pub struct PackState {
+    packs: Vec<levels::Pack>,  // built-in levels and all level packs found
+    grid: SelectionGrid,  // pack selection buttons
+}
+
+impl PackState {
+    // instantiate all entities of the current page
+    fn build_page(&mut self, world: &mut World) {
+        self.grid.build(world, "Select a level pack".to_string());
+
+        // packs with a broken manifest are shown in gray and cannot be selected
+        for index in self.grid.items() {
+            let pack = &self.packs[index];
+            self.grid.add_item(world, index, "pack-".to_string() + &pack.id, pack.name.clone(), pack.valid);
+        }
+    }
+}
+
+impl SimpleState for PackState {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.packs = levels::load_packs();
+        self.grid = SelectionGrid::new(self.packs.len());
+        self.build_page(data.world);
     }
 
     fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
@@ -166,22 +274,150 @@ impl SimpleState for LevelState {
                     x -= w * 0.5;
                     y = h * 0.5 - y;
 
-                    // if user clicks back button
-                    if let Some(btn) = &self.b_menu {
-                        if btn.in_range(x, y) {
+                    match self.grid.click(x, y) {
+                        GridClick::Menu => {
                             data.world.delete_all();  // clear entities before switching to new state
                             return Trans::Replace(Box::new(MenuState::default()));
                         }
+                        GridClick::Page => {
+                            data.world.delete_all();
+                            self.build_page(data.world);
+                        }
+                        GridClick::Item(index) if self.packs[index].valid => {
+                            data.world.delete_all();  // clear entities before switching to new state
+                            return Trans::Replace(Box::new(LevelState::new(self.packs[index].clone())));
+                        }
+                        _ => {}
                     }
+                }
+                Trans::None
+            }
+           _ => Trans::None
+        }
+    }
+}
 
-                    // if user selects a level
-                    for level in 1..(self.num_levels + 1) {
-                        if let Some(btn) = &self.b_games[level - 1] {
-                            if btn.in_range(x, y) {
-                                data.world.delete_all();  // clear entities before switching to new state
-                                return Trans::Switch(Box::new(GameState::new(level as u32)));
-                            }
+// Level
+#[derive(Default)]
+pub struct LevelState {
+    pack: levels::Pack,  // the level pack being shown
+    profile: save::Profile,  // unlocked levels and best scores
+    levels: Vec<levels::Level>,  // all levels listed in the level manifest
+    grid: SelectionGrid,  // level selection buttons
+    editing: bool,  // a selected level is opened in the editor instead of played
+    b_edit: Option<entities::Button>,  // edit toggle button data
+    // faded thumbnails by level path with the modification time of the file, so turning pages does not draw them again
+    thumbnails: std::collections::HashMap<String, (Option<std::time::SystemTime>, render::Canvas)>,
+}
+
+impl LevelState {
+    pub fn new(pack: levels::Pack) -> Self {
+        LevelState { pack, ..Default::default() }
+    }
+
+    // instantiate all entities of the current page
+    fn build_page(&mut self, world: &mut World) {
+        self.grid.build(world, self.pack.name.clone());
+        let text = if self.editing { "Edit: on" } else { "Edit: off" };
+        self.b_edit = Some(entities::Button::default(text.to_string(), 200., 60., "merriweather.ttf".to_string(), 24.));
+        self.b_edit.as_mut().unwrap().instantiate("edit".to_string(), world, 450., 200., 1.);
+
+        // levels that fail to load or are locked are shown in gray and cannot be selected
+        // (locked levels can still be edited)
+        for index in self.grid.items() {
+            let level = &self.levels[index];
+            let unlocked = self.profile.is_unlocked(level) || self.editing;
+            let (x, y) = self.grid.add_item(
+                world, index, "level-".to_string() + &level.info.id, level.info.title.clone(), level.valid && unlocked);
+
+            // a faded thumbnail of the level behind the title
+            if level.valid && unlocked {
+                if let Some(canvas) = thumbnail(&mut self.thumbnails, level) {
+                    let (width, height) = (canvas.width as f32 / utils::dpi(), canvas.height as f32 / utils::dpi());
+                    entities::Picture::new(canvas, width, height)
+                        .instantiate("level-thumbnail-".to_string() + &level.info.id, world, x, y, 0.95);
+                }
+            }
+
+            // best score or lock below the button
+            let record = self.profile.record(level);
+            let note = if !unlocked {
+                "Locked".to_string()
+            } else if let Some(best) = record.best_score {
+                format!("Best: {}", best)
+            } else {
+                "".to_string()
+            };
+            entities::Label::default(note, 160., 30., "merriweather.ttf".to_string(), 18.)
+                .instantiate("level-note-".to_string() + &level.info.id, world, x, y - 65., 1.);
+        }
+    }
+}
+
+// the faded thumbnail of a level, drawn again only if the file changed
+fn thumbnail(
+    thumbnails: &mut std::collections::HashMap<String, (Option<std::time::SystemTime>, render::Canvas)>,
+    level: &levels::Level,
+) -> Option<render::Canvas> {
+    let modified = std::fs::metadata(&level.path).and_then(|metadata| metadata.modified()).ok();
+    if let Some((time, canvas)) = thumbnails.get(&level.path) {
+        if *time == modified { return Some(canvas.clone()); }
+    }
+    let map = level.load_map().ok()?;
+    let (width, height) = (150., 90.);
+    let mut canvas = render::rasterize(&map, ((width * utils::dpi()) as u32, (height * utils::dpi()) as u32));
+    canvas.fade((255, 255, 255), 0.5);
+    thumbnails.insert(level.path.clone(), (modified, canvas.clone()));
+    Some(canvas)
+}
+
+impl SimpleState for LevelState {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        self.levels = self.pack.load_levels();
+        self.profile = save::Profile::load();
+        self.grid = SelectionGrid::new(self.levels.len());
+        self.build_page(data.world);
+    }
+
+    fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
+        // extract necessary input and dimensions data
+        let dimensions = (*data.world.read_resource::<ScreenDimensions>()).clone();
+        let (w, h) = (dimensions.width(), dimensions.height());
+        let mouse_pos = data.world.read_resource::<InputHandler<StringBindings>>().mouse_position();
+
+        match event {
+            // if the user clicks the left mouse button
+            StateEvent::Input(InputEvent::MouseButtonPressed(MouseButton::Left)) => {
+                if let Some((mut x, mut y)) = mouse_pos {
+                    // converts to game coords
+                    x -= w * 0.5;
+                    y = h * 0.5 - y;
+
+                    if self.b_edit.as_ref().map_or(false, |btn| btn.in_range(x, y)) {
+                        self.editing = !self.editing;
+                        data.world.delete_all();
+                        self.build_page(data.world);
+                        return Trans::None;
+                    }
+
+                    match self.grid.click(x, y) {
+                        GridClick::Menu => {
+                            data.world.delete_all();  // clear entities before switching to new state
+                            return Trans::Replace(Box::new(PackState::default()));
                         }
+                        GridClick::Item(index) if self.editing && self.levels[index].valid => {
+                            data.world.delete_all();
+                            return Trans::Replace(Box::new(editor::EditorState::new(self.levels[index].clone())));
+                        }
+                        GridClick::Page => {
+                            data.world.delete_all();
+                            self.build_page(data.world);
+                        }
+                        GridClick::Item(index) if self.levels[index].valid && self.profile.is_unlocked(&self.levels[index]) => {
+                            data.world.delete_all();  // clear entities before switching to new state
+                            return Trans::Switch(Box::new(GameState::new(self.levels[index].clone())));
+                        }
+                        _ => {}
                     }
                 }
                 Trans::None
@@ -200,37 +436,223 @@ impl Default for GameStatus {
     fn default() -> Self { GameStatus::None }
 }
 
+// The world state saved when a checkpoint is reached
+#[derive(Default)]
+struct CheckpointData {
+    name: String,
+    pos: (i32, i32),  // where the player respawns
+    snapshot: snapshot::Snapshot,
+}
+
+impl CheckpointData {
+    fn restore(&self, world: &mut World, map: &map::Map) {
+        self.snapshot.restore(world, map);
+        physics::respawn(world, self.pos);
+    }
+}
+
 #[derive(Default)]
 pub struct GameState {
-    level: u32,
+    level: levels::Level,
     scoreboard: entities::Scoreboard,
     map: Option<map::Map>,
+    timer: entities::Timer,
+    lives: u32,  // remaining tries
+    lives_hud: entities::HudText,
+    keys_hud: entities::HudText,
+    health_hud: entities::HudText,
+    checkpoint: CheckpointData,  // where to respawn (the start of the level if no checkpoint is reached)
+    history: snapshot::History,  // recent ticks for rewinding
+    play_test: bool,  // started from the editor, which is shown again when the game ends
+    modified: Option<std::time::SystemTime>,  // of the level file, which is reloaded when it changes
+    watch_ticks: u32,
+    error: Vec<Entity>,  // the overlay shown when the level file fails to load
 }
 
 impl GameState {
-    fn new(level: u32) -> Self {
-        GameState { level, scoreboard: entities::Scoreboard::default(), map: None }
-    }
-
-    // delete an object with the name
-    fn remove_objs(&mut self, world: &mut World, names: Vec<String>) {
-        let obj_store = world.read_storage::<components::ObjectComp>();
-        let mut entities_to_be_removed: Vec<Entity> = vec![];
-        for entity in (world.entities()).join() {
-            match obj_store.get(entity) {
-                Some(obj) => {
-                    if names.contains(&obj.name) {
-                        entities_to_be_removed.push(entity.clone());
+    fn new(level: levels::Level) -> Self {
+        GameState {
+            level, scoreboard: entities::Scoreboard::default(), map: None,
+            timer: entities::Timer::default(),
+            lives: 1, lives_hud: entities::HudText::default(), keys_hud: entities::HudText::default(),
+            health_hud: entities::HudText::default(),
+            checkpoint: CheckpointData::default(),
+            history: snapshot::History::default(),
+            play_test: false,
+            modified: None, watch_ticks: 0, error: vec![],
+        }
+    }
+
+    // play an unsaved map from the editor
+    pub fn play_test(level: levels::Level, map: map::Map) -> Self {
+        GameState { map: Some(map), play_test: true, ..GameState::new(level) }
+    }
+
+    fn modified_time(&self) -> Option<std::time::SystemTime> {
+        std::fs::metadata(&self.level.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    // whether the level file changed on disk since it was last loaded, checked every few ticks
+    // maps from the editor are not saved yet, so they are not watched
+    fn level_changed(&mut self) -> bool {
+        if self.play_test { return false; }
+        self.watch_ticks += 1;
+        if self.watch_ticks % utils::RELOAD_TICKS != 0 { return false; }
+        let modified = self.modified_time();
+        if modified == self.modified { return false; }
+        self.modified = modified;
+        true
+    }
+
+    // show why the level failed to load on top of the game, which stays paused until the file is fixed
+    fn show_error(&mut self, world: &mut World, err: &dyn std::error::Error) {
+        log::error!("Failed to load level \"{}\": {}", self.level.path, err);
+        self.clear_error(world);
+        self.error.push(entities::Background::new([1., 1., 1., 0.85]).instantiate_z("error_bg".to_string(), world, 2.));
+
+        // labels are a single line, so the message is wrapped by words
+        let mut lines = vec!["Failed to load the level".to_string()];
+        for word in err.to_string().split_whitespace() {
+            match lines.last_mut() {
+                Some(line) if lines.len() > 1 && line.len() + word.len() < 60 => { *line += " "; *line += word; }
+                _ => { lines.push(word.to_string()); }
+            }
+        }
+        lines.push("Fix the file and save it to reload".to_string());
+        let top = 20. * (lines.len() - 1) as f32;
+        for (i, line) in lines.into_iter().enumerate() {
+            let label = entities::Label::new(line, 1000., 40., utils::get_color(utils::RED), "merriweather.ttf".to_string(), 20.);
+            self.error.push(label.instantiate(format!("error_{}", i), world, 0., top - 40. * i as f32, 2.1));
+        }
+    }
+
+    fn clear_error(&mut self, world: &mut World) {
+        for ent in self.error.drain(..) {
+            world.delete_entity(ent).expect("Entity does not exist");
+        }
+    }
+
+    // rebuild the level from its file, the player stays where it was if it still fits there
+    fn reload(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) {
+        let map = match self.level.load_map() {
+            Ok(map) => map,
+            Err(err) => {
+                self.show_error(data.world, &*err);
+                return;
+            }
+        };
+        log::info!("Reloaded level \"{}\"", self.level.path);
+        let player = self.map.as_ref()
+            .and_then(|map| snapshot::Snapshot::capture(data.world, map, 0).player)
+            .map(|player| player.pos);
+        let (score, ticks, splits) = (self.scoreboard.score, self.timer.ticks, self.timer.splits.clone());
+        data.world.delete_all();
+        self.error.clear();
+        self.map = Some(map);
+        self.build(data);
+
+        if let Some(pos) = player {
+            let map = self.map.as_ref().unwrap();
+            if physics::fits(data.world, pos, map.player.size, map.size) {
+                {
+                    let mut uitrans_store = data.world.write_storage::<UiTransform>();
+                    let player_store = data.world.read_storage::<components::PlayerComp>();
+                    for (uitrans, _player) in (&mut uitrans_store, &player_store).join() {
+                        uitrans.local_x = pos.0 as f32 * utils::dpi();
+                        uitrans.local_y = pos.1 as f32 * utils::dpi();
                     }
                 }
-                None => {}
+
+                // the run goes on, so the score and the time are kept too
+                self.scoreboard.set_score(data.world, score);
+                self.checkpoint.snapshot.score = score;
+                self.timer.ticks = ticks;
+                self.timer.splits = splits;
             }
+            self.follow_player(data, 1.);
+        }
+    }
+
+    // initialize the objects and the HUD of the loaded map, for a new attempt
+    fn build(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) {
+        let map = self.map.as_ref().unwrap();
+        map.initialize(&mut data.world, &self.level.path);
+
+        // initialize scoreboard and timer
+        self.scoreboard = entities::Scoreboard::default();
+        self.scoreboard.instantiate("scoreboard".to_string(), data.world, 50., -60., 1.5);
+        self.timer = entities::Timer::new(save::Profile::load().record(&self.level).best_splits);
+        self.timer.instantiate("timer".to_string(), data.world, 50., -100., 1.5);
+
+        // lives, health and keys are only shown if the level uses them
+        let mut hud_y = -140.;
+        self.lives_hud = entities::HudText::default();
+        self.health_hud = entities::HudText::default();
+        self.keys_hud = entities::HudText::default();
+        self.lives = map.lives.max(1);
+        if self.lives > 1 {
+            self.lives_hud.instantiate(
+                "lives".to_string(), data.world, "Lives: ".to_string() + &self.lives.to_string(), 50., hud_y, 1.5);
+            hud_y -= 40.;
+        }
+        if map.uses_health() {
+            self.health_hud.instantiate("health".to_string(), data.world, "".to_string(), 50., hud_y, 1.5);
+            hud_y -= 40.;
+        }
+        if !map.keys.is_empty() {
+            self.keys_hud.instantiate("keys".to_string(), data.world, "Keys: -".to_string(), 50., hud_y, 1.5);
+        }
+        self.update_hud(data.world);
+        self.checkpoint = CheckpointData {
+            name: "".to_string(), pos: map.player.pos, snapshot: snapshot::Snapshot::capture(data.world, map, 0),
+        };
+        self.history = snapshot::History::default();
+        if let Some(rewind) = &map.rewind {
+            self.history = snapshot::History::new((rewind.seconds * utils::TICKS_PER_SECOND) as usize);
         }
 
-        drop(obj_store);
-        for ent in entities_to_be_removed.iter() {
-            world.delete_entity(*ent).expect("Entity does not exist");
+        // center camera
+        self.follow_player(data, utils::CAMERA_ALPHA);
+    }
+
+    // show the result in the pause menu, or go back to the editor when play-testing
+    fn conclude(&self, world: &mut World, status: GameStatus) -> SimpleTrans {
+        if self.play_test {
+            world.delete_all();
+            return Trans::Pop;
         }
+        Trans::Push(Box::new(PauseState::new(self.level.clone(), status, self.run())))
+    }
+
+    // lose a life and respawn at the last checkpoint, or lose the level if no life is left
+    fn die(&mut self, world: &mut World) -> SimpleTrans {
+        if self.lives <= 1 {
+            return self.conclude(world, GameStatus::Lose);
+        }
+        self.lives -= 1;
+        self.lives_hud.set_text(world, "Lives: ".to_string() + &self.lives.to_string());
+        self.checkpoint.restore(world, self.map.as_ref().unwrap());
+        self.scoreboard.set_score(world, self.checkpoint.snapshot.score);
+        self.history.clear();
+        Trans::None
+    }
+
+    // save the world state when a checkpoint is reached for the first time
+    fn reach_checkpoint(&mut self, world: &mut World, name: String) {
+        let map = self.map.as_ref().unwrap();
+        let pos = match map.checkpoints.iter().find(|cp| cp.name == name) {
+            Some(cp) => cp.pos,
+            None => { return; }
+        };
+        self.checkpoint = CheckpointData {
+            name: name.clone(), pos, snapshot: snapshot::Snapshot::capture(world, map, self.scoreboard.score),
+        };
+        self.timer.split(world, name);
+    }
+
+    // the current attempt, passed on to the pause menu
+    fn run(&self) -> save::Run {
+        save::Run { score: self.scoreboard.score, ticks: self.timer.ticks, splits: self.timer.splits.clone() }
     }
 
     // this function allows the camera to follow the player
@@ -270,87 +692,44 @@ impl GameState {
         }
     }
 
-    // helper function for finding the relative position between the player and other objects
-    // the result helps resolve collisions
-    fn get_rel_pos(&self, data: &StateData<'_, GameData<'_, '_>>) -> HashMap<String, Anchor> {
-        let uitrans_store = data.world.read_storage::<UiTransform>();
-        let player_store = data.world.read_storage::<components::PlayerComp>();
-        let obj_store = data.world.read_storage::<components::ObjectComp>();
-
-        let mut result = HashMap::new();
-
-        let mut player_uitrans: Option<&UiTransform> = None;
-        for (uitrans, _player) in (&uitrans_store, &player_store).join() {
-            player_uitrans = Some(uitrans);
-            break;
-        }
-
-        for (uitrans, obj) in (&uitrans_store, &obj_store).join() {
-            result.insert(obj.name.clone(),
-                utils::compare(player_uitrans.unwrap().clone(), uitrans.clone()));
-        }
+    fn update_hud(&self, world: &mut World) {
+        let keys = physics::inventory(world);
+        let text = if keys.is_empty() { "-".to_string() } else { keys.join(" ") };
+        self.keys_hud.set_text(world, "Keys: ".to_string() + &text);
+        let (health, max_health) = physics::health(world);
+        self.health_hud.set_text(world, format!("Health: {}/{}", health, max_health));
+    }
 
-        result
+    // go back by one tick, returns false if there is nothing left to rewind
+    fn rewind(&mut self, world: &mut World) -> bool {
+        let snapshot = match self.history.rewind() {
+            Some(snapshot) => snapshot.clone(),
+            None => { return false; }
+        };
+        snapshot.restore(world, self.map.as_ref().unwrap());
+        self.scoreboard.set_score(world, snapshot.score);
+        self.timer.rewind_to(world, snapshot.ticks);
+        true
     }
 
     fn check_win(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
-        // access the storage
-        let uitrans_store = data.world.read_storage::<UiTransform>();
-        let player_store = data.world.read_storage::<components::PlayerComp>();
-        let obj_store = data.world.read_storage::<components::ObjectComp>();
-        let inter_store = data.world.read_storage::<components::InteractableComp>();
-
-        // find the player
-        let mut _player_uitrans: Option<&UiTransform> = None;
-        for (uitrans, _player) in (&uitrans_store, &player_store).join() {
-            _player_uitrans = Some(uitrans);
-            break;
-        }
-        let player_uitrans = _player_uitrans.unwrap();
-
-        // check if player is out of bounds
-        if player_uitrans.local_y < utils::LOWER_BOUND as f32 * utils::dpi() {
-            return Trans::Push(Box::new(PauseState::new(self.level, GameStatus::Lose, self.scoreboard.score)));
+        let contacts = physics::check_contacts(data.world);
+        if contacts.dead || physics::hurt(data.world, contacts.hits) {
+            return self.die(data.world);
         }
-
-        // check if collide with enemy, target, or coin
-        for (uitrans, _inter, obj) in (&uitrans_store, &inter_store, &obj_store).join() {
-            match obj.type_ {
-                components::ObjectType::Monster => {
-                    if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {
-                        return Trans::Push(Box::new(PauseState::new(self.level, GameStatus::Lose, self.scoreboard.score)));
-                    }
-                }
-                components::ObjectType::Target => {
-                    if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {
-                        return Trans::Push(Box::new(PauseState::new(self.level, GameStatus::Win, self.scoreboard.score)));
-                    }
-                }
-                _ => {}
-            }
+        if contacts.won {
+            return self.conclude(data.world, GameStatus::Win);
         }
+        physics::remove_objs(data.world, contacts.projectiles);
 
-        let mut coins_to_remove: Vec<String> = vec![];
-        // check if collide with enemy, target, or coin
-        for (uitrans, _inter, obj) in (&uitrans_store, &inter_store, &obj_store).join() {
-            match obj.type_ {
-                components::ObjectType::Coin => {
-                    if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {
-                        // will be removed later
-                        // they cannot be removed now because is currently iterating though them
-                        coins_to_remove.push(obj.name.clone());
-                    }
-                }
-                _ => {}
-            }
-        }
+        // defeat stomped monsters
+        self.scoreboard.add_score(data.world, contacts.stomps.len() as i32 * utils::STOMP_SCORE);
+        physics::stomp(data.world, contacts.stomps);
 
-        drop(uitrans_store);
-        drop(player_store);
-        drop(obj_store);
-        drop(inter_store);
-        self.scoreboard.add_score(data.world, coins_to_remove.len() as i32);
-        self.remove_objs(&mut data.world, coins_to_remove);
+        // collect coins and keys
+        self.scoreboard.add_score(data.world, contacts.coins.len() as i32);
+        physics::remove_objs(&mut data.world, contacts.coins);
+        physics::collect_keys(&mut data.world, contacts.keys);
 
         Trans::None
     }
@@ -358,27 +737,25 @@ impl GameState {
 
 impl SimpleState for GameState {
     fn on_start(&mut self, mut data: StateData<'_, GameData<'_, '_>>) {
-        // load map
-        let app_root = application_root_dir().unwrap();
-        let level_path = app_root.join("assets").join("levels").join(self.level.to_string() + ".ron")
-            .into_os_string().into_string().unwrap();
-        self.map = Some(map::from_file(level_path).unwrap());
-
-        // initialize objects
-        let map = self.map.as_ref().unwrap();
-        map.initialize(&mut data.world);
-
-        // initialize scoreboard
-        self.scoreboard.instantiate("scoreboard".to_string(), data.world, 50., -60., 1.5);
-
-        // center camera
-        self.follow_player(&data, utils::CAMERA_ALPHA);
+        // load map and the fonts of its level pack
+        fonts::Fonts::instance().use_pack(data.world, &self.level.pack);
+        self.modified = self.modified_time();
+        if self.map.is_none() {
+            match self.level.load_map() {
+                Ok(map) => { self.map = Some(map); }
+                Err(err) => {
+                    self.show_error(data.world, &*err);
+                    return;
+                }
+            }
+        }
+        self.build(&mut data);
     }
 
-    fn handle_event(&mut self, _data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
+    fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
         if let StateEvent::Window(wevent) = &event {
             if is_key_down(&wevent, VirtualKeyCode::Escape) {
-                return Trans::Push(Box::new(PauseState::new(self.level, GameStatus::None, self.scoreboard.score)));
+                return self.conclude(data.world, GameStatus::None);
             }
         }
 
@@ -386,145 +763,75 @@ impl SimpleState for GameState {
     }
 
     fn fixed_update(&mut self, mut data: StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
-        // get relative position
-        let rel_pos = self.get_rel_pos(&data);
-
-        // access the storage
-        let input = data.world.read_resource::<InputHandler<StringBindings>>();
-        let mut uitrans_store = data.world.write_storage::<UiTransform>();
-        let mut player_store = data.world.write_storage::<components::PlayerComp>();
-        let obj_store = data.world.read_storage::<components::ObjectComp>();
-        let mut movable_store = data.world.write_storage::<components::MovableComp>();
-        let collidable_store = data.world.read_storage::<components::CollidableComp>();
-        let mut inter_store = data.world.write_storage::<components::InteractableComp>();
-
-        // find the player
-        let mut _player_comp: Option<&mut components::PlayerComp> = None;
-        for (_uitrans, player) in (&uitrans_store, &mut player_store).join() {
-            _player_comp = Some(player);
-            break;
+        if self.level_changed() {
+            self.reload(&mut data);
         }
-
-        // if the player is not found then there is some problem
-        if _player_comp.is_none() { println!("WARNING: PLAYER NOT FOUND!"); return Trans::None; }
-        let player_comp = _player_comp.unwrap();
-
-        // update player status
-        player_comp.speed.0 = 0;
-        if input.key_is_down(VirtualKeyCode::A) {
-            player_comp.speed.0 -= utils::PLAYER_SPEED;
-        }
-        if input.key_is_down(VirtualKeyCode::D) {
-            player_comp.speed.0 += utils::PLAYER_SPEED;
-        }
-        if input.key_is_down(VirtualKeyCode::W) {
-            if player_comp.can_jump && player_comp.jump_count > 0 {
-                player_comp.speed.1 = utils::PLAYER_JUMP;
-                player_comp.can_jump = false;
-                if !player_comp.on_ground { player_comp.jump_count -= 1; }
-            }
-        } else {
-            player_comp.can_jump = true;
-        }
-
-        // find movables and move them (and player)
-        let mut movables: Vec<&mut components::MovableComp> = Vec::new();
-        for (_uitrans, movable) in (&mut uitrans_store, &mut movable_store).join() {
-            movables.push(movable);
-        }
-        for movable in movables.iter_mut() { movable.move_(&obj_store, &mut uitrans_store); }
-        player_comp.move_(&obj_store, &mut uitrans_store);
-
-        // resolve collision (by changing the position of the player)
-        // will not deal with the case that the player is squeezed between two collidables
-        // the squeeze feature will be implemented in later versions
-        let mut _player_uitrans: Option<UiTransform> = None;
-        let mut target = (0., 0.);
-        for (uitrans, obj) in (&uitrans_store, &obj_store).join() {
-            if obj.name == player_comp.name {
-                _player_uitrans = Some(uitrans.clone());
-                target = (uitrans.local_x, uitrans.local_y);
-                break;
-            }
+        // nothing moves while the level file has an error
+        if self.map.is_none() || !self.error.is_empty() {
+            return Trans::None;
         }
-        let player_uitrans = _player_uitrans.unwrap();
-        player_comp.on_ground = false;
-        for (uitrans, collidable) in (&uitrans_store, &collidable_store).join() {
-            if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {  // TODO: get player moving track
-                let direction = utils::anchor_to_tuple(rel_pos[&collidable.name]);
-                if direction.1 != 0 {
-                    target.1 = uitrans.local_y
-                        + 0.5 * (direction.1 - 1) as f32 * uitrans.height
-                        + 0.5 * (direction.1 + 1) as f32 * player_uitrans.height;
-                    player_comp.speed.1 = 0;
-                    player_comp.on_ground = true;
-                    player_comp.jump_count = 1;
-                }
-                else if direction.0 != 0 {
-                    target.0 = uitrans.local_x
-                        + 0.5 * (direction.0 + 1) as f32 * uitrans.width
-                        + 0.5 * (direction.0 - 1) as f32 * player_uitrans.width;  // player modifier
-                    player_comp.speed.0 = 0;
-                }
-            }
+        self.timer.tick(data.world);
+
+        // hold R to rewind if the level allows it, which turns back the timer too
+        let rewinding = self.map.as_ref().unwrap().rewind.is_some()
+            && data.world.read_resource::<InputHandler<StringBindings>>().key_is_down(VirtualKeyCode::R);
+        if rewinding && self.rewind(&mut data.world) {
+            self.follow_player(&data, utils::CAMERA_ALPHA);
+            self.update_hud(data.world);
+            return Trans::None;
         }
 
-        // update resolved player position
-        for (uitrans, _player) in (&mut uitrans_store, &player_store).join() {
-            uitrans.local_x = target.0;
-            uitrans.local_y = target.1;
-        }
-
-        // check if buttons are pressed
-        let mut pressed_buttons: Vec<components::InteractableComp> = vec![];
-        for (uitrans, inter) in (&uitrans_store, &mut inter_store).join() {
-            if utils::compare(player_uitrans.clone(), uitrans.clone()) == Anchor::Middle {
-                pressed_buttons.push(inter.clone());
-            }
-        }
+        // move everything according to the pressed keys
+        let input = physics::Input::from_keys(&data.world.read_resource::<InputHandler<StringBindings>>());
+        let pressed_buttons = physics::step(&mut data.world, &input);
 
         // center camera
-        drop(input);
-        drop(uitrans_store);
-        drop(player_store);
-        drop(movable_store);
-        drop(obj_store);
-        drop(collidable_store);
-        drop(inter_store);
         self.follow_player(&data, utils::CAMERA_ALPHA);
-        
-        // execute commands
-        for inter in pressed_buttons.iter_mut() {
-            inter.exec(&mut data.world);
+
+        // save the world when reaching a checkpoint for the first time
+        for inter in pressed_buttons.iter() {
+            if inter.state == "close" && self.map.as_ref().unwrap().checkpoints.iter().any(|cp| cp.name == inter.name) {
+                self.reach_checkpoint(&mut data.world, inter.name.clone());
+            }
         }
 
         // check win or lose
-        self.check_win(&mut data)
+        let trans = self.check_win(&mut data);
+
+        // remember this tick for rewinding
+        if self.map.as_ref().unwrap().rewind.is_some() {
+            let mut snapshot = snapshot::Snapshot::capture(data.world, self.map.as_ref().unwrap(), self.scoreboard.score);
+            snapshot.ticks = self.timer.ticks;
+            self.history.record(snapshot);
+        }
+        self.update_hud(data.world);
+        trans
     }
 }
 
 // Pause: the in-game menu when the game is paused or concluded
 #[derive(Default)]
 pub struct PauseState {
-    level: u32,  // the level currently playing
+    level: levels::Level,  // the level currently playing
     status: GameStatus,  // win, lose, or pause
-    score: i32,
+    run: save::Run,  // score, time and splits of the current attempt
     b_game: Option<entities::Button>,  // resume/replay button data
     b_menu: Option<entities::Button>,  // main menu button data
     ent_bg: Option<Entity>,  // the corresponding entities
     ent_title: Option<Entity>,
     ent_status: Option<Entity>,
     ent_score: Option<Entity>,
+    ent_time: Option<Entity>,
     ent_b_game: Option<Entity>,
     ent_b_menu: Option<Entity>,
 }
 
 impl PauseState {
-    pub fn new(level: u32, status: GameStatus, score: i32) -> Self {
+    pub fn new(level: levels::Level, status: GameStatus, run: save::Run) -> Self {
         PauseState {
-            level, status, score, b_game: None, b_menu: None,
+            level, status, run, b_game: None, b_menu: None,
             ent_bg: None, ent_title: None, ent_status: None,
-            ent_score: None, ent_b_game: None, ent_b_menu: None
+            ent_score: None, ent_time: None, ent_b_game: None, ent_b_menu: None
         }
     }
 }
@@ -532,8 +839,39 @@ impl PauseState {
 impl SimpleState for PauseState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
         // decide displayed messages
-        let title_msg = "Level ".to_string() + &self.level.to_string();
-        let score_msg = "Score: ".to_string() + &self.score.to_string();
+        let title_msg = self.level.info.title.clone();
+        let mut score_msg = "Score: ".to_string() + &self.run.score.to_string();
+        let mut time_msg = "Time: ".to_string() + &utils::format_ticks(self.run.ticks);
+
+        // save the result of a concluded game
+        match self.status {
+            GameStatus::None => {}
+            _ => {
+                let mut profile = save::Profile::load();
+                let record = profile.record(&self.level);
+                let won = matches!(self.status, GameStatus::Win);
+                let result = profile.record_run(&self.level, won, &self.run);
+                if result.best_score {
+                    score_msg += "  (New best!)";
+                } else if let Some(best) = record.best_score {
+                    score_msg += &format!("  (Best: {})", best);
+                }
+                if result.best_time {
+                    time_msg += "  (New PB!)";
+                } else if let Some(best) = record.best_time {
+                    time_msg += &format!("  (PB: {})", utils::format_ticks(best));
+                }
+                if let Err(err) = profile.save() {
+                    log::error!("Failed to save progress: {}", err);
+                }
+                if won {
+                    match save::export_splits(&self.level, &self.run) {
+                        Ok(path) => { log::info!("Splits exported to \"{}\"", path.display()); }
+                        Err(err) => { log::error!("Failed to export splits: {}", err); }
+                    }
+                }
+            }
+        }
         let (status_msg, b_game_msg, bg_color) = match self.status {
             GameStatus::Win => { ("You Win!", "Replay", [192, 128, 128, 192]) }
             GameStatus::Lose => { ("You Lost!", "Replay", [128, 128, 192, 192]) }
@@ -550,16 +888,19 @@ impl SimpleState for PauseState {
         // instantiate the status and the scoreboard
         self.ent_status = Some(entities::Label::default(
             status_msg.to_string(), 600., 200., "cambria.ttf".to_string(), 25.)
-            .instantiate("status".to_string(), data.world, 0., 90., 3.));
+            .instantiate("status".to_string(), data.world, 0., 100., 3.));
         self.ent_score = Some(entities::Label::default(
             score_msg.to_string(), 600., 200., "cambria.ttf".to_string(), 25.)
-            .instantiate("score".to_string(), data.world, 0., 50., 3.));
+            .instantiate("score".to_string(), data.world, 0., 65., 3.));
+        self.ent_time = Some(entities::Label::default(
+            time_msg.to_string(), 600., 200., "cambria.ttf".to_string(), 25.)
+            .instantiate("time".to_string(), data.world, 0., 30., 3.));
 
         // instantiate the buttons
         self.b_game = Some(entities::Button::default(
             b_game_msg.to_string(), 260., 50., "merriweather.ttf".to_string(), 25.));
         self.ent_b_game = Some(
-            self.b_game.as_mut().unwrap().instantiate("game".to_string(), data.world, 0., -20., 3.));
+            self.b_game.as_mut().unwrap().instantiate("game".to_string(), data.world, 0., -40., 3.));
         self.b_menu = Some(entities::Button::default(
             "Exit to menu".to_string(), 260., 50., "merriweather.ttf".to_string(), 25.));
         self.ent_b_menu = Some(
@@ -571,6 +912,7 @@ impl SimpleState for PauseState {
         data.world.delete_entity(self.ent_title.unwrap()).unwrap_or(());
         data.world.delete_entity(self.ent_status.unwrap()).unwrap_or(());
         data.world.delete_entity(self.ent_score.unwrap()).unwrap_or(());
+        data.world.delete_entity(self.ent_time.unwrap()).unwrap_or(());
         data.world.delete_entity(self.ent_b_game.unwrap()).unwrap_or(());
         data.world.delete_entity(self.ent_b_menu.unwrap()).unwrap_or(());
     }
@@ -606,7 +948,7 @@ impl SimpleState for PauseState {
                                 GameStatus::None => { return Trans::Pop; }
                                 _ => { 
                                     data.world.delete_all();
-                                    return Trans::Replace(Box::new(GameState::new(self.level)));
+                                    return Trans::Replace(Box::new(GameState::new(self.level.clone())));
                                 }
                             }
                         }
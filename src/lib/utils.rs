@@ -3,6 +3,7 @@ use amethyst::{
     ui::{ Anchor, UiTransform },
 };
 use amethyst_rendy::palette::Srgba;
+use std::path::PathBuf;
 
 static mut _DPI: f32 = 1.;
 
@@ -17,12 +18,27 @@ pub fn set_dpi(new_dpi: f32) {
     }
 }
 
+// per-user directory for saves and user-installed content
+pub fn data_dir() -> PathBuf {
+    let home = std::env::var("HOME").map(PathBuf::from).unwrap_or(PathBuf::from("."));
+    let base = if cfg!(target_os = "windows") {
+        std::env::var("APPDATA").map(PathBuf::from).unwrap_or(home)
+    } else if cfg!(target_os = "macos") {
+        home.join("Library").join("Application Support")
+    } else {
+        std::env::var("XDG_DATA_HOME").map(PathBuf::from).unwrap_or(home.join(".local").join("share"))
+    };
+    base.join("platformer")
+}
+
 pub const BACKGROUND_COLOR: [u32; 4] = [60, 179, 113, 255];
 pub const BLACK: [u32; 4] = [0, 0, 0, 255];
 pub const WHITE: [u32; 4] = [255, 255, 255, 255];
 pub const GRAY_1: [u32; 4] = [224, 224, 224, 255];
 pub const GRAY_2: [u32; 4] = [192, 192, 192, 255];
 pub const GRAY_3: [u32; 4] = [128, 128, 128, 255];
+pub const RED: [u32; 4] = [192, 0, 0, 255];
+pub const GREEN: [u32; 4] = [0, 128, 0, 255];
 
 pub fn get_color([r, g, b, a]: [u32; 4]) -> [f32; 4] {
     let (new_r, new_g, new_b, _) = Srgba::new(
@@ -36,8 +52,26 @@ pub const CAMERA_ALPHA: f32 = 0.1;
 pub const PLAYER_SPEED: i32 = 8;
 pub const PLAYER_JUMP: i32 = 30;
 pub const GRAVITY: i32 = 2;
+pub const PLAYER_HEALTH: u32 = 3;
+pub const INVULNERABLE_TICKS: u32 = 60;
+pub const KNOCKBACK: (i32, i32) = (12, 16);
+pub const STOMP_BOUNCE: i32 = 20;
+pub const STOMP_SCORE: i32 = 2;
+pub const SPRING_MIN_COOLDOWN: u32 = 10;  // keeps the active color of a spring visible
 pub const LOWER_BOUND: i32 = -200;
 
+pub const LEVELS_PER_PAGE: usize = 8;
+
+// fixed updates run at the default rate of amethyst
+pub const TICKS_PER_SECOND: u32 = 60;
+pub const RELOAD_TICKS: u32 = 30;  // how often the level file is checked for changes
+
+// format a number of ticks as m:ss.cc
+pub fn format_ticks(ticks: u32) -> String {
+    let centis = ticks * 100 / TICKS_PER_SECOND;
+    format!("{}:{:02}.{:02}", centis / 6000, centis / 100 % 60, centis % 100)
+}
+
 pub fn anchor_to_tuple(anchor: Anchor) -> (i32, i32) {
     match anchor {
         Anchor::BottomLeft => { (-1, -1) }
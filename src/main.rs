@@ -11,7 +11,7 @@ use amethyst::{
     utils::application_root_dir,
 };
 
-mod lib;
+use platformer::states;
 
 fn main() -> amethyst::Result<()> {
     // find the paths to important locations
@@ -32,7 +32,7 @@ fn main() -> amethyst::Result<()> {
             .with_plugin(RenderFlat2D::default()))?;
 
     // start the game with InitState
-    let mut game = Application::new(resources_path, lib::states::InitState::default(), game_data)?;
+    let mut game = Application::new(resources_path, states::InitState::default(), game_data)?;
     game.run();
 
     Ok(())